@@ -0,0 +1,31 @@
+#[macro_use]
+extern crate criterion;
+extern crate stfu8;
+
+use criterion::{black_box, Criterion};
+
+fn binary() -> Vec<u8> {
+    (0..0x1_0000_u32).map(|i| (i % 256) as u8).collect()
+}
+
+fn text() -> Vec<u8> {
+    let mut out = Vec::new();
+    while out.len() < 0x1_0000 {
+        out.extend_from_slice("foo bar\tbaz ¡ ¢ £\\ 𠜎\n".as_bytes());
+    }
+    out
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let binary = stfu8::encode_u8(&binary());
+    let text = stfu8::encode_u8(&text());
+    c.bench_function("decode_u8 binary", |b| {
+        b.iter(|| stfu8::decode_u8(black_box(&binary)).unwrap())
+    });
+    c.bench_function("decode_u8 text", |b| {
+        b.iter(|| stfu8::decode_u8(black_box(&text)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
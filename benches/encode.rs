@@ -0,0 +1,43 @@
+#[macro_use]
+extern crate criterion;
+extern crate stfu8;
+
+use criterion::{black_box, Criterion};
+
+fn binary() -> Vec<u8> {
+    (0..0x1_0000_u32).map(|i| (i % 256) as u8).collect()
+}
+
+fn text() -> Vec<u8> {
+    let mut out = Vec::new();
+    while out.len() < 0x1_0000 {
+        out.extend_from_slice("foo bar\tbaz ¡ ¢ £\\ 𠜎\n".as_bytes());
+    }
+    out
+}
+
+fn log() -> Vec<u8> {
+    let mut out = Vec::new();
+    while out.len() < 0x1_0000 {
+        out.extend_from_slice(b"2018-01-01T00:00:00Z INFO [server] request handled in 12ms\n");
+    }
+    out
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let binary = binary();
+    let text = text();
+    let log = log();
+    c.bench_function("encode_u8 binary", |b| {
+        b.iter(|| stfu8::encode_u8(black_box(&binary)))
+    });
+    c.bench_function("encode_u8 text", |b| {
+        b.iter(|| stfu8::encode_u8(black_box(&text)))
+    });
+    c.bench_function("encode_u8 log", |b| {
+        b.iter(|| stfu8::encode_u8_pretty(black_box(&log)))
+    });
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
@@ -0,0 +1,166 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+use std::fmt::Write;
+
+use crate::helpers;
+use crate::{DecodeError, DecodeErrorKind};
+
+/// Tag for data encoded as STFU-8.
+const TAG_TEXT: char = 'T';
+/// Tag for data encoded as a hex dump.
+const TAG_HEX: char = 'X';
+
+/// Encode data as STFU-8 if it is mostly text, otherwise as a plain (uppercase) hex dump.
+///
+/// The output is prefixed with a one character tag: `T` for STFU-8 or `X` for hex. STFU-8 is
+/// used whenever it is no longer than the hex dump would be, so text stays readable while binary
+/// stays compact.
+///
+/// Decode with [`decode_adaptive`](fn.decode_adaptive.html).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(stfu8::encode_adaptive(b"foo\nbar"), r"Tfoo\nbar");
+/// assert_eq!(stfu8::encode_adaptive(b"\x00\x01\xFF"), "X0001FF");
+/// # }
+/// ```
+pub fn encode_adaptive(v: &[u8]) -> String {
+    let text = crate::encode_u8(v);
+    let mut out = String::with_capacity(1 + text.len().min(v.len() * 2));
+    if text.len() <= v.len() * 2 {
+        out.push(TAG_TEXT);
+        out.push_str(&text);
+    } else {
+        out.push(TAG_HEX);
+        for b in v {
+            write!(out, "{:0>2X}", b).unwrap();
+        }
+    }
+    out
+}
+
+/// Decode the output of [`encode_adaptive`](fn.encode_adaptive.html).
+///
+/// A missing or unknown tag is an [`InvalidTag`](enum.DecodeErrorKind.html#variant.InvalidTag)
+/// error.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(stfu8::decode_adaptive(r"Tfoo\nbar").unwrap(), b"foo\nbar");
+/// assert_eq!(stfu8::decode_adaptive("X0001ff").unwrap(), b"\x00\x01\xFF");
+/// assert!(stfu8::decode_adaptive("foo").is_err());
+/// # }
+/// ```
+pub fn decode_adaptive(s: &str) -> Result<Vec<u8>, DecodeError> {
+    let mut chars = s.chars();
+    let tag = chars.next();
+    let rest = chars.as_str();
+    match tag {
+        Some(TAG_TEXT) => {
+            crate::decode_u8(rest).map_err(|err| err.offset(TAG_TEXT.len_utf8()).locate(s))
+        }
+        Some(TAG_HEX) => decode_hex(rest).map_err(|err| err.locate(s)),
+        _ => Err(DecodeError::new(DecodeErrorKind::InvalidTag, 0, s.to_string()).locate(s)),
+    }
+}
+
+/// Decode a hex dump which is after the tag.
+fn decode_hex(s: &str) -> Result<Vec<u8>, DecodeError> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len() / 2);
+    for (i, pair) in bytes.chunks(2).enumerate() {
+        // index in the original (tagged) input
+        let index = 1 + i * 2;
+        if pair.len() < 2 {
+            return Err(DecodeError::new(
+                DecodeErrorKind::HexNumberToShort,
+                index,
+                s[(i * 2)..].to_string(),
+            ));
+        }
+        match helpers::from_hex(pair) {
+            Some(b) => out.push(b as u8),
+            None => {
+                return Err(DecodeError::new(
+                    DecodeErrorKind::InvalidHexDigit,
+                    index,
+                    String::from_utf8_lossy(&bytes[(i * 2)..]).into_owned(),
+                ));
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic "random" bytes.
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut state: u32 = 0x1234_5678;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sanity_adaptive_text() {
+        let text = "foo bar\n\tbaz ¡ ¢ £\\ ".as_bytes();
+        let encoded = encode_adaptive(text);
+        assert!(encoded.starts_with('T'), "{}", encoded);
+        assert_eq!(decode_adaptive(&encoded).unwrap(), text);
+    }
+
+    #[test]
+    fn sanity_adaptive_binary() {
+        let binary = random_bytes(1024);
+        let encoded = encode_adaptive(&binary);
+        assert!(encoded.starts_with('X'), "{}", encoded);
+        assert_eq!(encoded.len(), 1 + binary.len() * 2);
+        assert_eq!(decode_adaptive(&encoded).unwrap(), binary);
+    }
+
+    #[test]
+    fn sanity_adaptive_empty() {
+        assert_eq!(encode_adaptive(b""), "T");
+        assert_eq!(decode_adaptive("T").unwrap(), b"");
+        assert_eq!(decode_adaptive("X").unwrap(), b"");
+    }
+
+    #[test]
+    fn sanity_adaptive_errors() {
+        let err = decode_adaptive("").unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::InvalidTag);
+        let err = decode_adaptive("Zfoo").unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::InvalidTag);
+
+        let err = decode_adaptive(r"Tfoo\bar").unwrap_err();
+        assert_eq!(err.column(), 5);
+        assert_eq!((err.kind, err.index), (DecodeErrorKind::UnescapedSlash, 4));
+
+        let err = decode_adaptive("X00F").unwrap_err();
+        assert_eq!(
+            (err.kind, err.index),
+            (DecodeErrorKind::HexNumberToShort, 3)
+        );
+        let err = decode_adaptive("X00FG").unwrap_err();
+        assert_eq!(err.matched(), "FG");
+        assert_eq!((err.kind, err.index), (DecodeErrorKind::InvalidHexDigit, 3));
+    }
+}
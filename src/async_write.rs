@@ -0,0 +1,103 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! Async support, enabled with the `futures` feature.
+
+use std::io;
+
+use futures::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::decode::{self, PushGeneric};
+use crate::Decoder;
+
+/// Decode a UTF-8 string containing encoded STFU-8, writing the binary to an `AsyncWrite`.
+///
+/// Decoding is done synchronously from the in-memory `&str`; only the writes are awaited. Runs
+/// of unescaped text are written directly from `s` without being copied.
+///
+/// A [`DecodeError`](struct.DecodeError.html) is returned as an `io::Error` of kind
+/// `InvalidData`. Anything before the error has already been written.
+///
+/// # Examples
+/// ```rust
+/// # extern crate futures;
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let mut out: Vec<u8> = Vec::new();
+/// futures::executor::block_on(
+///     stfu8::decode_u8_to_async_writer(r"foo\xFF\nbar", &mut out)
+/// ).unwrap();
+/// assert_eq!(out, b"foo\xFF\nbar");
+/// # }
+/// ```
+pub async fn decode_u8_to_async_writer<W>(s: &str, w: &mut W) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let decoder = Decoder::new();
+    let mut string = s;
+    let mut offset = 0;
+    let mut buf: Vec<u8> = Vec::new();
+
+    while !string.is_empty() {
+        let consumed_bytes = match string.find(decoder.escape_char) {
+            Some(0) => {
+                buf.clear();
+                let mut f = |val: PushGeneric| decode::push_u8(&mut buf, val);
+                let consumed =
+                    decode::decode_escape(&decoder, &mut f, string, offset).map_err(|err| {
+                        let err = err.match_escape(&decoder, s).locate(s);
+                        io::Error::new(io::ErrorKind::InvalidData, err)
+                    })?;
+                w.write_all(&buf).await?;
+                consumed
+            }
+            Some(byte_index) => {
+                w.write_all(&string.as_bytes()[..byte_index]).await?;
+                byte_index
+            }
+            None => {
+                w.write_all(string.as_bytes()).await?;
+                string.len()
+            }
+        };
+        string = &string[consumed_bytes..];
+        offset += consumed_bytes;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::decode_u8_to_async_writer;
+
+    fn decode(s: &str) -> Result<Vec<u8>, ::std::io::Error> {
+        let mut out: Vec<u8> = Vec::new();
+        block_on(decode_u8_to_async_writer(s, &mut out))?;
+        Ok(out)
+    }
+
+    #[test]
+    fn sanity_async_writer() {
+        for s in &["", "foo", r"foo\xFF\nbar", r"\\\t\x00", r"¡ ¢ £ \u02070E ¤"] {
+            assert_eq!(decode(s).unwrap(), crate::decode_u8(s).unwrap());
+        }
+    }
+
+    #[test]
+    fn sanity_async_writer_error() {
+        let err = decode("foo\n\\bar").unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::InvalidData);
+        let err = err.into_inner().unwrap();
+        let err = err.downcast_ref::<crate::DecodeError>().unwrap();
+        assert_eq!(err.index, 4);
+        assert_eq!((err.line(), err.column()), (2, 1));
+    }
+}
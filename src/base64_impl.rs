@@ -0,0 +1,99 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! `base64` support, enabled with the `base64` feature.
+
+use std::str;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::{DecodeError, DecodeErrorKind};
+
+/// Encode bytes as STFU-8 and then encode that text as (standard, padded) base64.
+///
+/// Decode with [`decode_u8_base64`](fn.decode_u8_base64.html).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// // the base64 of `foo\xFF`
+/// assert_eq!(stfu8::encode_u8_base64(b"foo\xFF"), "Zm9vXHhGRg==");
+/// # }
+/// ```
+pub fn encode_u8_base64(v: &[u8]) -> String {
+    STANDARD.encode(crate::encode_u8(v))
+}
+
+/// Decode base64 (standard, padded) containing STFU-8 text, then decode the STFU-8.
+///
+/// Invalid base64 is a [`Base64`](enum.DecodeErrorKind.html#variant.Base64) error with the
+/// `index` into `s`. If the base64 does not decode to UTF-8 it is a
+/// [`NotUtf8`](enum.DecodeErrorKind.html#variant.NotUtf8) error. For these and any error from
+/// decoding the STFU-8, the `index` is into the base64-decoded text.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(stfu8::decode_u8_base64("Zm9vXHhGRg==").unwrap(), b"foo\xFF");
+///
+/// let err = stfu8::decode_u8_base64("Zm9v!").unwrap_err();
+/// assert_eq!(*err.kind(), stfu8::DecodeErrorKind::Base64);
+/// assert_eq!(err.index(), 4);
+/// # }
+/// ```
+pub fn decode_u8_base64(s: &str) -> Result<Vec<u8>, DecodeError> {
+    let text = STANDARD.decode(s).map_err(|err| {
+        let index = match err {
+            base64::DecodeError::InvalidByte(i, _)
+            | base64::DecodeError::InvalidLastSymbol(i, _) => i,
+            base64::DecodeError::InvalidLength(_) | base64::DecodeError::InvalidPadding => s.len(),
+        };
+        DecodeError::new(DecodeErrorKind::Base64, index, s[index..].to_string()).locate(s)
+    })?;
+    let text = str::from_utf8(&text).map_err(|err| {
+        let index = err.valid_up_to();
+        let mat = String::from_utf8_lossy(&text[index..]).into_owned();
+        DecodeError::new(DecodeErrorKind::NotUtf8, index, mat)
+    })?;
+    crate::decode_u8(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_u8_base64, encode_u8_base64};
+    use crate::DecodeErrorKind;
+
+    #[test]
+    fn sanity_base64_roundtrip() {
+        let samples: Vec<&[u8]> = vec![b"", b"foo", b"foo\xFF\n\\bar", "¡ ¢ £".as_bytes()];
+        for v in samples {
+            let encoded = encode_u8_base64(v);
+            assert_eq!(decode_u8_base64(&encoded).unwrap(), v);
+        }
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode_u8_base64(&encode_u8_base64(&all)).unwrap(), all);
+    }
+
+    #[test]
+    fn sanity_base64_error() {
+        let err = decode_u8_base64("Zm9").unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::Base64);
+
+        // base64 of `\xFF`, which is not UTF-8
+        let err = decode_u8_base64("/w==").unwrap_err();
+        assert_eq!((err.kind, err.index), (DecodeErrorKind::NotUtf8, 0));
+
+        // base64 of `foo\bar`
+        let err = decode_u8_base64("Zm9vXGJhcg==").unwrap_err();
+        assert_eq!((err.kind, err.index), (DecodeErrorKind::UnescapedSlash, 3));
+    }
+}
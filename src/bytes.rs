@@ -0,0 +1,67 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+use std::convert::TryFrom;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use crate::DecodeError;
+
+/// Bytes decoded from (and, with the `serde` feature, serialized as) a STFU-8 encoded string.
+///
+/// Parsing uses [`decode_u8`](fn.decode_u8.html). With the `serde` feature this allows binary
+/// data which is *mostly* text to be stored in human readable formats like JSON or TOML.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let bytes: stfu8::Stfu8Bytes = r"foo\xFF\nbar".parse().unwrap();
+/// assert_eq!(&*bytes, b"foo\xFF\nbar");
+/// assert_eq!(bytes.len(), 8);
+///
+/// let err = "foo\\bar".parse::<stfu8::Stfu8Bytes>().unwrap_err();
+/// assert_eq!(*err.kind(), stfu8::DecodeErrorKind::UnescapedSlash);
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Stfu8Bytes(pub Vec<u8>);
+
+impl Deref for Stfu8Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl FromStr for Stfu8Bytes {
+    type Err = DecodeError;
+
+    fn from_str(s: &str) -> Result<Stfu8Bytes, DecodeError> {
+        crate::decode_u8(s).map(Stfu8Bytes)
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Stfu8Bytes {
+    type Error = DecodeError;
+
+    fn try_from(s: &'a str) -> Result<Stfu8Bytes, DecodeError> {
+        s.parse()
+    }
+}
+
+#[test]
+fn sanity_bytes_parse() {
+    let bytes = Stfu8Bytes::try_from(r"\t¡\x00").unwrap();
+    assert_eq!(bytes, Stfu8Bytes(b"\t\xC2\xA1\x00".to_vec()));
+    assert_eq!(bytes[1..], b"\xC2\xA1\x00"[..]);
+    assert!(Stfu8Bytes::try_from(r"\xFG").is_err());
+    assert_eq!("".parse::<Stfu8Bytes>().unwrap(), Stfu8Bytes::default());
+}
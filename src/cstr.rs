@@ -0,0 +1,136 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! Encoding of nul-terminated `CStr`, for interop with C APIs.
+
+use std::ffi::{CStr, CString};
+
+use crate::decode::{self, PushGeneric};
+use crate::{DecodeError, DecodeErrorKind, Decoder};
+
+/// Encode a `CStr` as STFU-8.
+///
+/// Only the bytes up to (not including) the terminating nul are encoded, so the output never
+/// ends with a `\x00`. Use [`decode_to_cstring`](fn.decode_to_cstring.html) to get it back.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+/// use std::ffi::CStr;
+///
+/// # fn main() {
+/// let c = CStr::from_bytes_with_nul(b"foo\xFF\tbar\0").unwrap();
+/// let encoded = stfu8::encode_cstr(c);
+/// assert_eq!(encoded, r"foo\xFF\tbar");
+/// assert_eq!(stfu8::decode_to_cstring(&encoded).unwrap().as_c_str(), c);
+/// # }
+/// ```
+pub fn encode_cstr(c: &CStr) -> String {
+    crate::encode_u8(c.to_bytes())
+}
+
+/// Decode STFU-8 into a `CString`, which has a nul appended after the decoded bytes.
+///
+/// A `CString` can't hold any other nul, so if the decoded bytes contain one (i.e. from a `\x00`
+/// escape) it is an [`InteriorNul`](enum.DecodeErrorKind.html#variant.InteriorNul) error at the
+/// index of the escape (or literal nul) in `s`, instead of silently truncating the data.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let c = stfu8::decode_to_cstring(r"foo\xFF").unwrap();
+/// assert_eq!(c.as_bytes_with_nul(), b"foo\xFF\0");
+///
+/// let err = stfu8::decode_to_cstring(r"foo\x00bar").unwrap_err();
+/// assert_eq!(*err.kind(), stfu8::DecodeErrorKind::InteriorNul);
+/// assert_eq!(err.index(), 3);
+/// # }
+/// ```
+pub fn decode_to_cstring(s: &str) -> Result<CString, DecodeError> {
+    let decoder = Decoder::new();
+    let mut out: Vec<u8> = Vec::with_capacity(s.len() + 1);
+    // the index of the end of the previous item, where a `Char` escape starts
+    let mut next = 0;
+    // an escape is matched once decoding stops
+    let interior_nul =
+        |index: usize| DecodeError::new(DecodeErrorKind::InteriorNul, index, String::new());
+    {
+        let f = |val: PushGeneric| -> Result<(), DecodeError> {
+            match val {
+                PushGeneric::Value { start, val: 0 } => return Err(interior_nul(start)),
+                PushGeneric::Value { start, .. } => {
+                    next = start + escape_len(&decoder, &s[start..])
+                }
+                PushGeneric::String(text) => {
+                    let start = text.as_ptr() as usize - s.as_ptr() as usize;
+                    if let Some(i) = text.find('\0') {
+                        let err =
+                            DecodeError::new(DecodeErrorKind::InteriorNul, start + i, "\0".into());
+                        return Err(err);
+                    }
+                    next = start + text.len();
+                }
+                PushGeneric::Char('\0') => return Err(interior_nul(next)),
+                PushGeneric::Char(_) => next += escape_len(&decoder, &s[next..]),
+                PushGeneric::Bytes { starts, bytes } => {
+                    if let Some(i) = bytes.iter().position(|b| *b == 0) {
+                        return Err(interior_nul(starts[i]));
+                    }
+                    let last = starts[starts.len() - 1];
+                    next = last + escape_len(&decoder, &s[last..]);
+                }
+            }
+            decode::push_u8(&mut out, val)
+        };
+        decode::decode_generic(&decoder, f, s)?;
+    }
+    Ok(CString::new(out).expect("nul bytes were rejected"))
+}
+
+/// The length in bytes of the valid escape at the start of `s`.
+fn escape_len(decoder: &Decoder, s: &str) -> usize {
+    // escapes are ASCII, so their width in chars is their length
+    decode::escape_width(decoder, s)
+}
+
+#[test]
+fn sanity_cstr_roundtrip() {
+    let c = CStr::from_bytes_with_nul(b"C:\\foo\n\xC2\xA1\xFF\0").unwrap();
+    let encoded = encode_cstr(c);
+    assert_eq!(encoded, r"C:\\foo\n¡\xFF");
+    assert_eq!(decode_to_cstring(&encoded).unwrap().as_c_str(), c);
+    assert_eq!(encode_cstr(CStr::from_bytes_with_nul(b"\0").unwrap()), "");
+    assert_eq!(decode_to_cstring("").unwrap().as_bytes_with_nul(), b"\0");
+}
+
+#[test]
+fn sanity_cstr_interior_nul() {
+    for &(s, index) in &[
+        ("\0", 0),
+        ("ab\0", 2),
+        (r"ab\x00", 2),
+        (r"\0", 0),
+        (r"a\x41\x42\x00", 9),
+        (r"¡\n\u000000", 4),
+        (r"a\u{41}b\x41\u{0}", 12),
+        (r"\\\U00000000", 2),
+    ] {
+        let err = decode_to_cstring(s).unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::InteriorNul, index),
+            "{}",
+            s
+        );
+        assert_eq!(err.matched(), &s[index..]);
+    }
+    // other errors are still reported
+    let err = decode_to_cstring(r"a\q").unwrap_err();
+    assert_eq!(err.kind, DecodeErrorKind::UnescapedSlash);
+}
@@ -7,27 +7,300 @@
  */
 
 use std::char;
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
+use std::str;
 
-#[derive(Debug, PartialEq)]
+use crate::helpers;
+use crate::{EscapeStyle, SpecEntry};
+
+// UTF-16 surrogates, as the value of a `\u` escape
+const LEAD_MIN: u32 = 0xD800;
+const LEAD_MAX: u32 = 0xDBFF;
+const TRAIL_MIN: u32 = 0xDC00;
+const TRAIL_MAX: u32 = 0xDFFF;
+
+/// The byte order mark, see `Decoder::strip_bom`.
+const BOM: char = '\u{FEFF}';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum DecodeErrorKind {
-    /// A single unescaped backslash was found. Either the following character doesn't
-    /// start a valid escape sequence or it is at the end of the string.
+    /// A single unescaped backslash was found: the following character doesn't start a valid
+    /// escape sequence.
     UnescapedSlash,
+    /// A single unescaped backslash was found at the end of the string, i.e. an incomplete
+    /// escape.
+    TrailingBackslash,
     /// The value from a '\x' or '\u' hexadecimal escape sequence is out of range for the decode.
     InvalidValue,
     /// There are not enough characters after a '\x' or '\u' to build a escape sequence.
     HexNumberToShort,
     /// The required characters after a '\x' or '\u' are not all valid hex digits.
     InvalidHexDigit,
+    /// The escaped bytes do not form valid UTF-8 when decoding into a `String`.
+    NotUtf8,
+    /// The data does not start with a known tag when decoding adaptively encoded data, or its
+    /// profile header is invalid.
+    InvalidTag,
+    /// The value from a '\u' escape is not a unicode scalar value and the decoder rejects them.
+    NotAScalar,
+    /// The data is not valid base64 when decoding STFU-8 wrapped in base64.
+    Base64,
+    /// Nested STFU-8 still has escapes after the decoder's maximum number of levels.
+    TooDeeplyNested,
+    /// A `\x` or `\u` escape is directly followed by another hex digit, which may have been
+    /// intended as part of it. Only when the decoder rejects overlong escapes.
+    OverlongEscape,
+    /// The input has a control character (below `U+0020`) which is not escaped. Only when the
+    /// decoder rejects literal controls.
+    LiteralControl,
+    /// The decoded data has a nul byte, which a `CString` can't hold.
+    InteriorNul,
+    /// A `\x` or `\u` escape has a lowercase hex digit. Only when the decoder requires uppercase
+    /// hex.
+    NonCanonicalHex,
+    /// The braces of a `\u{X}` escape have no hex digits, i.e. `\u{}`.
+    EmptyBraceEscape,
+    /// A `\N{NAME}` escape has no closing brace or names no unicode character. Only with the
+    /// `unicode-names` feature.
+    UnknownUnicodeName,
+    /// The input has the escape character of another dialect (`\` or `%`) which is not escaped,
+    /// so it was likely written for a different decoder. Only when the decoder has a strict
+    /// escape character.
+    MixedEscapeChar,
 }
 
-#[derive(Debug)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct DecodeError {
+    #[deprecated(note = "use kind()/index()")]
     pub kind: DecodeErrorKind,
+    #[deprecated(note = "use kind()/index()")]
     pub index: usize,
     pub(crate) mat: String,
+    line: usize,
+    column: usize,
+}
+
+impl DecodeError {
+    #[allow(deprecated)]
+    pub(crate) fn new(kind: DecodeErrorKind, index: usize, mat: String) -> DecodeError {
+        DecodeError {
+            kind,
+            index,
+            mat,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    /// Create an [`InvalidValue`](enum.DecodeErrorKind.html#variant.InvalidValue) error for the
+    /// escape at `index`, i.e. for a [`DecodedItem::Value`](enum.DecodedItem.html) which a custom
+    /// sink can't hold.
+    pub fn invalid_value(index: usize) -> DecodeError {
+        DecodeError::new(DecodeErrorKind::InvalidValue, index, String::new())
+    }
+
+    /// Match the escape at the index of the error in the original input, unless something more
+    /// specific is already matched.
+    ///
+    /// Only the escape itself is kept as the match, not the rest of the input.
+    pub(crate) fn match_escape(mut self, decoder: &super::Decoder, input: &str) -> DecodeError {
+        if self.mat.is_empty() && self.index() < input.len() {
+            let rest = &input[self.index()..];
+            self.mat = rest.chars().take(escape_width(decoder, rest)).collect();
+        }
+        self
+    }
+
+    /// Compute the line and column of the error from the original input.
+    pub(crate) fn locate(self, s: &str) -> DecodeError {
+        Locator::new(s).locate(self)
+    }
+
+    /// Move the index of the error by `offset`, for input which was decoded starting at `offset`.
+    #[allow(deprecated)]
+    pub(crate) fn offset(mut self, offset: usize) -> DecodeError {
+        self.index += offset;
+        self
+    }
+
+    /// The kind of error.
+    #[allow(deprecated)]
+    pub fn kind(&self) -> &DecodeErrorKind {
+        &self.kind
+    }
+
+    /// The byte index of the input where the error occurred.
+    #[allow(deprecated)]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The line (starting at 1) of the input where the error occurred.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The column (starting at 1) of the input where the error occurred, counted in `char`s.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// The text which caused the error.
+    ///
+    /// This is the input at [`index`](#method.index). For an invalid escape it is only the
+    /// escape itself, and for a rejected literal character only that character.
+    pub fn matched(&self) -> &str {
+        &self.mat
+    }
+
+    /// Render the error against its `input` in a format similar to `rustc`, with a caret
+    /// underlining the offending escape.
+    ///
+    /// `input` must be the string which was decoded.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # extern crate stfu8;
+    ///
+    /// # fn main() {
+    /// let input = "foo\n\nbar \\q";
+    /// let err = stfu8::decode_u8(input).unwrap_err();
+    /// let expected = "\
+    /// error: Found unmatched '\\'. Use \"\\\\\" to escape slashes
+    ///  --> 3:5
+    ///   |
+    /// 3 | bar \\q
+    ///   |     ^^
+    /// ";
+    /// assert_eq!(err.render(input), expected);
+    /// # }
+    /// ```
+    pub fn render(&self, input: &str) -> String {
+        let index = self.index().min(input.len());
+        let line_start = input[..index].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[index..].find('\n').map_or(input.len(), |i| index + i);
+        let line = input[line_start..line_end].trim_end_matches('\r');
+        let line_num = input[..index].matches('\n').count() + 1;
+        let before = &input[line_start..index];
+        let gutter = " ".repeat(line_num.to_string().len());
+
+        // keep tabs so that the caret lines up with the source line
+        let pad: String = before
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // the match is the escape, except for a lowercase digit which is inside of it
+        let width = match self.kind() {
+            DecodeErrorKind::NonCanonicalHex => 1,
+            _ => self
+                .mat
+                .chars()
+                .count()
+                .min(input[index..line_end].chars().count())
+                .max(1),
+        };
+
+        format!(
+            "error: {kind}\n{gutter}--> {line_num}:{column}\n{gutter} |\n\
+             {line_num} | {line}\n{gutter} | {pad}{carets}\n",
+            kind = self.kind().as_str(),
+            gutter = gutter,
+            line_num = line_num,
+            column = before.chars().count() + 1,
+            line = line,
+            pad = pad,
+            carets = "^".repeat(width),
+        )
+    }
+}
+
+/// Locates errors in the original input.
+///
+/// Errors which are located in increasing order of index only scan the input once.
+pub(crate) struct Locator<'a> {
+    s: &'a str,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Locator<'a> {
+    pub(crate) fn new(s: &'a str) -> Locator<'a> {
+        Locator {
+            s,
+            index: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Compute the line and column of `err`, continuing from the previously located error.
+    pub(crate) fn locate(&mut self, mut err: DecodeError) -> DecodeError {
+        if err.index() < self.index {
+            *self = Locator::new(self.s);
+        }
+        let between = &self.s[self.index..err.index()];
+        match between.rfind('\n') {
+            Some(i) => {
+                self.line += between.matches('\n').count();
+                self.column = between[(i + 1)..].chars().count() + 1;
+            }
+            None => self.column += between.chars().count(),
+        }
+        self.index = err.index();
+        err.line = self.line;
+        err.column = self.column;
+        err
+    }
+}
+
+/// The number of `char`s of the (possibly invalid) escape of the `decoder` at the start of `s`,
+/// at least one.
+pub(crate) fn escape_width(decoder: &super::Decoder, s: &str) -> usize {
+    let is_digit = |c: &char| c.is_ascii_alphanumeric();
+    let mut chars = s.chars();
+    if decoder.escape_style == EscapeStyle::Percent {
+        chars.next();
+        return 1 + chars.take(2).take_while(is_digit).count();
+    }
+    let kind = match (chars.next(), chars.next()) {
+        (None, _) => return 1,
+        (Some(_), None) => return 1,
+        (Some(_), Some(kind)) => kind,
+    };
+    if let Some(entry) = decoder
+        .escape_spec
+        .find(kind as u8)
+        .filter(|_| kind.is_ascii())
+    {
+        // a prefix which is a digit is also the first digit
+        let start = if kind.is_digit(entry.radix) { 1 } else { 2 };
+        return start
+            + s[start..]
+                .chars()
+                .take(entry.max_digits)
+                .take_while(is_digit)
+                .count();
+    }
+    let max_digits = match kind {
+        'x' => decoder.x_digits,
+        'u' if chars.clone().next() == Some('{') => {
+            let brace: String = chars.take(8).collect();
+            return 2 + brace.find('}').map_or(1, |end| end + 1);
+        }
+        'N' if chars.clone().next() == Some('{') => {
+            let name = chars.as_str();
+            return 2 + name.find('}').map_or(1, |end| name[..=end].chars().count());
+        }
+        'u' if decoder.short_u_escapes => 4,
+        'u' => 6,
+        'U' => 8,
+        _ => 0,
+    };
+    2 + chars.take(max_digits).take_while(is_digit).count()
 }
 
 pub(crate) enum PushGeneric<'a> {
@@ -35,143 +308,880 @@ pub(crate) enum PushGeneric<'a> {
     Value { start: usize, val: u32 },
     /// Push an always-valid string.
     String(&'a str),
+    /// Push a single char decoded from a `\u` escape.
+    Char(char),
+    /// Push the bytes of consecutive escapes, each of which started at the matching index of
+    /// `starts`.
+    Bytes {
+        starts: &'a [usize],
+        bytes: &'a [u8],
+    },
+}
+
+/// An item of decoded STFU-8, passed to the sink of [`decode_into`](fn.decode_into.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodedItem<'a> {
+    /// Text from the input which was not escaped.
+    Str(&'a str),
+    /// A char from a `\u` escape of a unicode scalar value.
+    Char(char),
+    /// The value of an escape at `index` of the input.
+    ///
+    /// This is a byte from a `\x` or shorthand escape, or a `\u` escape which is not a unicode
+    /// scalar value (i.e. a surrogate), so it may be *any* `u32`. Values which the sink can't
+    /// hold should be rejected with [`DecodeError::invalid_value`](struct.DecodeError.html#method.invalid_value).
+    Value { index: usize, value: u32 },
+}
+
+/// Decode into a custom sink, see [`decode_into`](fn.decode_into.html).
+pub(crate) fn decode_into<F>(decoder: &super::Decoder, s: &str, mut f: F) -> Result<(), DecodeError>
+where
+    F: FnMut(DecodedItem) -> Result<(), DecodeError>,
+{
+    let push_val = |val: PushGeneric| match val {
+        PushGeneric::Value { start, val } => f(DecodedItem::Value {
+            index: start,
+            value: val,
+        }),
+        // the remainder of the input is pushed even if it is empty
+        PushGeneric::String("") => Ok(()),
+        PushGeneric::String(s) => f(DecodedItem::Str(s)),
+        PushGeneric::Char(c) => f(DecodedItem::Char(c)),
+        PushGeneric::Bytes { starts, bytes } => {
+            for (start, b) in starts.iter().zip(bytes) {
+                f(DecodedItem::Value {
+                    index: *start,
+                    value: u32::from(*b),
+                })?;
+            }
+            Ok(())
+        }
+    };
+    decode_generic(decoder, push_val, s)
+}
+
+/// Bytes from consecutive escapes, pushed together as a single `PushGeneric::Bytes`.
+#[derive(Default)]
+struct Run {
+    starts: Vec<usize>,
+    bytes: Vec<u8>,
+}
+
+impl Run {
+    fn flush<F>(&mut self, push_val: &mut F) -> Result<(), DecodeError>
+    where
+        F: FnMut(PushGeneric) -> Result<(), DecodeError>,
+    {
+        if !self.bytes.is_empty() {
+            push_val(PushGeneric::Bytes {
+                starts: &self.starts,
+                bytes: &self.bytes,
+            })?;
+            self.starts.clear();
+            self.bytes.clear();
+        }
+        Ok(())
+    }
 }
 
 /// Decode generically
-pub(crate) fn decode_generic<F>(mut push_val: F, s: &str) -> Result<(), DecodeError>
+pub(crate) fn decode_generic<F>(
+    decoder: &super::Decoder,
+    push_val: F,
+    s: &str,
+) -> Result<(), DecodeError>
+where
+    F: FnMut(PushGeneric) -> Result<(), DecodeError>,
+{
+    decode_escapes(decoder, push_val, Err, s).map_err(|err| err.locate(s))
+}
+
+/// Decode generically, passing each error to `on_error` and skipping the invalid escape instead
+/// of stopping.
+///
+/// Values which were pushed before an escape failed (i.e. the first bytes of a long `\x` escape)
+/// are kept.
+pub(crate) fn decode_generic_recover<F, E>(
+    decoder: &super::Decoder,
+    push_val: F,
+    mut on_error: E,
+    s: &str,
+) where
+    F: FnMut(PushGeneric) -> Result<(), DecodeError>,
+    E: FnMut(DecodeError),
+{
+    // the errors are found in order, so they are located without rescanning the input
+    let mut locator = Locator::new(s);
+    let on_error = |err: DecodeError| {
+        on_error(locator.locate(err));
+        Ok(())
+    };
+    decode_escapes(decoder, push_val, on_error, s).expect("all errors are recovered from");
+}
+
+/// The byte of the value `val` of the escape at `start`, or an `InvalidValue` error if it is not
+/// one.
+pub(crate) fn value_u8(start: usize, val: u32) -> Result<u8, DecodeError> {
+    u8::try_from(val).map_err(|_| DecodeError::invalid_value(start))
+}
+
+/// Check that a value can be pushed into decoded binary, without pushing it.
+pub(crate) fn check_u8(val: &PushGeneric) -> Result<(), DecodeError> {
+    if let PushGeneric::Value { val, start } = *val {
+        value_u8(start, val)?;
+    }
+    Ok(())
+}
+
+/// Push a value into decoded binary.
+pub(crate) fn push_u8(out: &mut Vec<u8>, val: PushGeneric) -> Result<(), DecodeError> {
+    match val {
+        PushGeneric::Value { val, start } => {
+            out.push(value_u8(start, val)?);
+            Ok(())
+        }
+        PushGeneric::String(s) => {
+            out.extend_from_slice(s.as_bytes());
+            Ok(())
+        }
+        PushGeneric::Char(c) => {
+            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            Ok(())
+        }
+        PushGeneric::Bytes { bytes, .. } => {
+            out.extend_from_slice(bytes);
+            Ok(())
+        }
+    }
+}
+
+/// The length of the bytes `decode_u8` returns, without storing them.
+pub(crate) fn decoded_len_u8(decoder: &super::Decoder, s: &str) -> Result<usize, DecodeError> {
+    let mut len = 0;
+    decode_generic(
+        decoder,
+        |val| {
+            check_u8(&val)?;
+            len += match val {
+                PushGeneric::Value { .. } => 1,
+                PushGeneric::String(s) => s.len(),
+                PushGeneric::Char(c) => c.len_utf8(),
+                PushGeneric::Bytes { bytes, .. } => bytes.len(),
+            };
+            Ok(())
+        },
+        s,
+    )?;
+    Ok(len)
+}
+
+/// An iterator over the bytes decoded from STFU-8, created by
+/// [`decode_u8_iter`](fn.decode_u8_iter.html).
+///
+/// The input is decoded lazily, one escape at a time. After an error it yields nothing else.
+#[derive(Debug, Clone)]
+pub struct DecodeU8Iter<'a> {
+    decoder: super::Decoder,
+    s: &'a str,
+    /// The index of `s` to decode next.
+    pub(crate) index: usize,
+    /// The bytes decoded from the previous escape, which are being yielded.
+    buf: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<'a> DecodeU8Iter<'a> {
+    pub(crate) fn new(decoder: super::Decoder, s: &'a str) -> DecodeU8Iter<'a> {
+        DecodeU8Iter {
+            decoder,
+            s,
+            index: 0,
+            buf: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<'a> Iterator for DecodeU8Iter<'a> {
+    type Item = Result<u8, DecodeError>;
+
+    fn next(&mut self) -> Option<Result<u8, DecodeError>> {
+        // an escape may decode to nothing (a line continuation) or to several bytes (a char)
+        while self.pos >= self.buf.len() {
+            if self.done || self.index >= self.s.len() {
+                return None;
+            }
+            let b = self.s.as_bytes()[self.index];
+            if b != self.decoder.escape_char as u8 {
+                self.index += 1;
+                return Some(Ok(b));
+            }
+            self.buf.clear();
+            self.pos = 0;
+            let buf = &mut self.buf;
+            let mut push_val = |val: PushGeneric| push_u8(buf, val);
+            match decode_escape(
+                &self.decoder,
+                &mut push_val,
+                &self.s[self.index..],
+                self.index,
+            ) {
+                Ok(consumed) => self.index += consumed,
+                Err(err) => {
+                    self.done = true;
+                    let err = err.match_escape(&self.decoder, self.s);
+                    return Some(Err(err.locate(self.s)));
+                }
+            }
+        }
+        self.pos += 1;
+        Some(Ok(self.buf[self.pos - 1]))
+    }
+}
+
+/// Decode the STFU-8 in `buf` into binary in place, see
+/// [`decode_u8_in_place`](fn.decode_u8_in_place.html).
+pub(crate) fn decode_u8_in_place(
+    decoder: &super::Decoder,
+    buf: &mut Vec<u8>,
+) -> Result<(), DecodeError> {
+    let s = str::from_utf8(buf).map_err(|err| {
+        let index = err.valid_up_to();
+        let mat = String::from_utf8_lossy(&buf[index..]).into_owned();
+        let valid = str::from_utf8(&buf[..index]).expect("valid up to the index");
+        DecodeError::new(DecodeErrorKind::NotUtf8, index, mat).locate(valid)
+    })?;
+    // check everything first, so that `buf` is unchanged on error
+    decode_generic(decoder, |val| check_u8(&val), s)?;
+
+    // Each escape is decoded from a window of the input which holds all of it (the longest is a
+    // recombined surrogate pair) and the byte after it, even if the window ends in the middle of
+    // a char. Escapes never decode to more bytes than they are written with, so the decoded
+    // bytes can be written over the part of `buf` which was already read.
+    let window = 16.max(2 + decoder.x_digits) + 4;
+    let escape = decoder.escape_char as u8;
+    let mut read = 0;
+    if decoder.strip_bom && s.starts_with(BOM) {
+        read = BOM.len_utf8();
+    }
+    let mut write = 0;
+    let mut decoded = Vec::new();
+    while let Some(i) = buf[read..].iter().position(|b| *b == escape) {
+        let start = read + i;
+        write = copy_literal(decoder, buf, read, start, write);
+
+        let mut end = buf.len().min(start + window);
+        if cfg!(feature = "unicode-names") && buf[(start + 1)..].starts_with(b"N{") {
+            // a `\N{NAME}` escape is only bounded by its closing brace
+            end = match buf[start..].iter().position(|b| *b == b'}') {
+                Some(i) => buf.len().min(start + i + 2),
+                None => buf.len(),
+            };
+        }
+        let text = match str::from_utf8(&buf[start..end]) {
+            Ok(text) => text,
+            Err(err) => str::from_utf8(&buf[start..(start + err.valid_up_to())]).unwrap(),
+        };
+        decoded.clear();
+        let mut push_val = |val: PushGeneric| push_u8(&mut decoded, val);
+        let consumed =
+            decode_escape(decoder, &mut push_val, text, start).expect("the input is valid");
+        buf[write..(write + decoded.len())].copy_from_slice(&decoded);
+        write += decoded.len();
+        read = start + consumed;
+    }
+    let len = buf.len();
+    write = copy_literal(decoder, buf, read, len, write);
+    buf.truncate(write);
+    Ok(())
+}
+
+/// Copy the unescaped text `buf[start..end]` back to `write`, returning the index after it.
+fn copy_literal(
+    decoder: &super::Decoder,
+    buf: &mut [u8],
+    start: usize,
+    end: usize,
+    mut write: usize,
+) -> usize {
+    if !decoder.normalize_crlf {
+        buf.copy_within(start..end, write);
+        return write + end - start;
+    }
+    for i in start..end {
+        if buf[i] == b'\r' && i + 1 < end && buf[i + 1] == b'\n' {
+            continue;
+        }
+        buf[write] = buf[i];
+        write += 1;
+    }
+    write
+}
+
+fn decode_escapes<F, E>(
+    decoder: &super::Decoder,
+    mut push_val: F,
+    mut on_error: E,
+    s: &str,
+) -> Result<(), DecodeError>
 where
     F: FnMut(PushGeneric) -> Result<(), DecodeError>,
+    E: FnMut(DecodeError) -> Result<(), DecodeError>,
 {
     let mut string = s;
     let mut offset = 0;
+    if decoder.strip_bom && s.starts_with(BOM) {
+        string = &s[BOM.len_utf8()..];
+        offset = BOM.len_utf8();
+    }
+    let mut run = Run::default();
+
+    // the values of a run are rejected once it is flushed
+    let flush = |run: &mut Run, push_val: &mut F| {
+        run.flush(push_val)
+            .map_err(|err| err.match_escape(decoder, s))
+    };
 
-    while let Some(byte_index) = string.find('\\') {
+    while let Some(byte_index) = string.find(decoder.escape_char) {
         if byte_index > 0 {
-            push_val(PushGeneric::String(&string[..byte_index]))?;
+            flush(&mut run, &mut push_val)?;
+            let text = &string[..byte_index];
+            push_literal(decoder, &mut push_val, &mut on_error, text, offset)?;
         }
         // byte index of the backslash in the original string
         let start_idx = offset + byte_index;
-        let rest = string.len() - byte_index;
-        if rest < 2 {
-            Err(DecodeError {
-                index: start_idx,
-                kind: DecodeErrorKind::UnescapedSlash,
-                mat: string[byte_index..].to_string(),
-            })?
+        // collect escaped bytes into the run, anything else ends it
+        let mut push_run = |val: PushGeneric| match val {
+            PushGeneric::Value { start, val } if val <= u32::from(u8::MAX) => {
+                run.starts.push(start);
+                run.bytes.push(val as u8);
+                Ok(())
+            }
+            val => {
+                run.flush(&mut push_val)?;
+                push_val(val)
+            }
+        };
+        let escape = &string[byte_index..];
+        let consumed_bytes = match decode_escape(decoder, &mut push_run, escape, start_idx) {
+            Ok(consumed) => consumed,
+            Err(err) => {
+                on_error(err.match_escape(decoder, s))?;
+                // skip the invalid escape
+                escape
+                    .chars()
+                    .take(escape_width(decoder, escape))
+                    .map(char::len_utf8)
+                    .sum()
+            }
+        };
+
+        string = &string[(byte_index + consumed_bytes)..];
+        offset += byte_index + consumed_bytes;
+    }
+    flush(&mut run, &mut push_val)?;
+    push_literal(decoder, &mut push_val, &mut on_error, string, offset)
+}
+
+/// Push the text at `offset` of the input which was not escaped, checking it for literal control
+/// characters and the other dialect's escape character if the decoder rejects them.
+fn push_literal<F, E>(
+    decoder: &super::Decoder,
+    push_val: &mut F,
+    on_error: &mut E,
+    mut text: &str,
+    mut offset: usize,
+) -> Result<(), DecodeError>
+where
+    F: FnMut(PushGeneric) -> Result<(), DecodeError>,
+    E: FnMut(DecodeError) -> Result<(), DecodeError>,
+{
+    let other_escape = if decoder.escape_char == helpers::BSLASH {
+        '%'
+    } else {
+        helpers::BSLASH
+    };
+    let is_control = |c: char| c < ' ' && !helpers::is_pretty_whitespace(c as u8);
+    let is_rejected = |c: char| {
+        decoder.reject_literal_controls && is_control(c)
+            || decoder.strict_escape_char && c == other_escape
+    };
+    if decoder.reject_literal_controls || decoder.strict_escape_char {
+        while let Some(i) = text.find(is_rejected) {
+            if i > 0 {
+                push_text(decoder, push_val, &text[..i])?;
+            }
+            let kind = if text[i..].starts_with(other_escape) {
+                DecodeErrorKind::MixedEscapeChar
+            } else {
+                DecodeErrorKind::LiteralControl
+            };
+            on_error(DecodeError::new(kind, offset + i, text[i..=i].to_string()))?;
+            // skip the character, which is a single byte
+            text = &text[(i + 1)..];
+            offset += i + 1;
         }
+    }
+    push_text(decoder, push_val, text)
+}
 
-        // macro to create a PushGeneric::Value
-        macro_rules! pg_value {
-            ( $v:expr ) => {{
-                PushGeneric::Value {
-                    start: start_idx,
-                    val: $v as u32,
-                }
-            }};
-        }
-        let consumed_bytes = match &string.as_bytes()[byte_index + 1] {
-            b't' => {
-                push_val(pg_value!(b'\t'))?;
-                2
-            }
-            b'n' => {
-                push_val(pg_value!(b'\n'))?;
-                2
-            }
-            b'r' => {
-                push_val(pg_value!(b'\r'))?;
-                2
-            }
-            b'\\' => {
-                push_val(pg_value!(b'\\'))?;
-                2
-            }
-            b'x' => {
-                if rest < 4 {
-                    Err(DecodeError {
-                        index: start_idx,
-                        kind: DecodeErrorKind::HexNumberToShort,
-                        mat: string[byte_index..].to_string(),
-                    })?
-                }
+/// Push text which was not escaped, collapsing each `\r\n` to `\n` if the decoder normalizes
+/// them.
+fn push_text<F>(
+    decoder: &super::Decoder,
+    push_val: &mut F,
+    mut text: &str,
+) -> Result<(), DecodeError>
+where
+    F: FnMut(PushGeneric) -> Result<(), DecodeError>,
+{
+    if decoder.normalize_crlf {
+        while let Some(i) = text.find("\r\n") {
+            if i > 0 {
+                push_val(PushGeneric::String(&text[..i]))?;
+            }
+            // keep the line feed as the start of the rest
+            text = &text[(i + 1)..];
+        }
+    }
+    push_val(PushGeneric::String(text))
+}
 
-                match u32::from_str_radix(&string[(byte_index + 2)..(byte_index + 4)], 16) {
-                    Ok(x) => push_val(pg_value!(x)),
-                    Err(_) => Err(DecodeError {
-                        index: start_idx,
-                        kind: DecodeErrorKind::InvalidHexDigit,
-                        mat: s.to_string(),
-                    }),
-                }?;
-                4
-            }
-            b'u' => {
-                if rest < 8 {
-                    Err(DecodeError {
-                        index: start_idx,
-                        kind: DecodeErrorKind::HexNumberToShort,
-                        mat: string[byte_index..].to_string(),
-                    })?
-                }
+/// Decode the single escape at the start of `string`, returning the number of bytes consumed.
+///
+/// `start_idx` is the index of `string` in the original input, used for errors.
+pub(crate) fn decode_escape<F>(
+    decoder: &super::Decoder,
+    push_val: &mut F,
+    string: &str,
+    start_idx: usize,
+) -> Result<usize, DecodeError>
+where
+    F: FnMut(PushGeneric) -> Result<(), DecodeError>,
+{
+    debug_assert!(string.starts_with(decoder.escape_char));
+    let rest = string.len();
+    if rest < 2 {
+        Err(DecodeError::new(
+            DecodeErrorKind::TrailingBackslash,
+            start_idx,
+            String::new(),
+        ))?
+    }
 
-                let c32 = match u32::from_str_radix(&string[(byte_index + 2)..(byte_index + 8)], 16)
-                {
-                    Ok(x) => Ok(x),
-                    Err(_) => Err(DecodeError {
-                        index: start_idx,
-                        kind: DecodeErrorKind::InvalidHexDigit,
-                        mat: s.to_string(),
-                    }),
-                }?;
+    if decoder.escape_style == EscapeStyle::Percent {
+        return decode_percent_escape(decoder, push_val, string, start_idx);
+    }
+    if let Some(entry) = decoder.escape_spec.find(string.as_bytes()[1]) {
+        return decode_spec_escape(entry, push_val, string, start_idx);
+    }
+    if let Some(value) = decoder.escape_spec.find_shorthand(string.as_bytes()[1]) {
+        push_val(PushGeneric::Value {
+            start: start_idx,
+            val: u32::from(value),
+        })?;
+        return Ok(2);
+    }
 
-                match char::from_u32(c32) {
-                    // It is a valid UTF code point. Always
-                    // decode it as such.
-                    Some(c) => push_val(PushGeneric::String(&c.to_string())),
-                    // It is not a valid code point. Still try
-                    // to record it's value "as is".
-                    None => push_val(pg_value!(c32)),
+    // macro to create a PushGeneric::Value
+    macro_rules! pg_value {
+        ( $v:expr ) => {{
+            PushGeneric::Value {
+                start: start_idx,
+                val: $v as u32,
+            }
+        }};
+    }
+    // escapes are ascii, so index the bytes directly
+    let bytes = string.as_bytes();
+    let consumed_bytes = match bytes[1] {
+        b't' => {
+            push_val(pg_value!(b'\t'))?;
+            2
+        }
+        b'n' => {
+            push_val(pg_value!(b'\n'))?;
+            2
+        }
+        b'r' => {
+            push_val(pg_value!(b'\r'))?;
+            2
+        }
+        #[cfg(feature = "unicode-names")]
+        b'N' if bytes.get(2) == Some(&b'{') => {
+            let (c, consumed) = decode_name_escape(string, start_idx)?;
+            if decoder.strict_u8 {
+                push_val(pg_value!(c))?;
+            } else {
+                push_val(PushGeneric::Char(c))?;
+            }
+            consumed
+        }
+        b'T' if decoder.case_insensitive_shorthands => {
+            push_val(pg_value!(b'\t'))?;
+            2
+        }
+        b'N' if decoder.case_insensitive_shorthands => {
+            push_val(pg_value!(b'\n'))?;
+            2
+        }
+        b'R' if decoder.case_insensitive_shorthands => {
+            push_val(pg_value!(b'\r'))?;
+            2
+        }
+        b'0' => {
+            push_val(pg_value!(0x00))?;
+            2
+        }
+        // a line continuation
+        b'\n' if decoder.allow_line_continuation => 2,
+        b'\r' if decoder.allow_line_continuation && bytes.get(2) == Some(&b'\n') => 3,
+        // the escape character escapes itself
+        b if b == decoder.escape_char as u8 => {
+            push_val(pg_value!(b))?;
+            2
+        }
+        b'x' => {
+            // the final escape of the input is allowed to be short
+            let digits = if rest - 2 < decoder.x_digits && (rest - 2).is_multiple_of(2) {
+                rest - 2
+            } else {
+                decoder.x_digits
+            };
+            if digits == 0 || rest < 2 + digits {
+                Err(DecodeError::new(
+                    DecodeErrorKind::HexNumberToShort,
+                    start_idx,
+                    String::new(),
+                ))?
+            }
+
+            let hex = &bytes[2..(2 + digits)];
+            for i in (0..digits).step_by(2) {
+                match helpers::from_hex(&hex[i..(i + 2)]) {
+                    Some(x) => {
+                        check_uppercase(decoder, string, 2 + i + 2, start_idx)?;
+                        push_val(pg_value!(x))
+                    }
+                    None => Err(DecodeError::new(
+                        DecodeErrorKind::InvalidHexDigit,
+                        start_idx,
+                        String::new(),
+                    )),
                 }?;
-                8
             }
-            _ => Err(DecodeError {
-                index: start_idx,
-                kind: DecodeErrorKind::UnescapedSlash,
-                mat: string[byte_index..].to_string(),
-            })?,
+            2 + digits
+        }
+        b'u' | b'U' => {
+            let digits = if decoder.short_u_escapes { 4 } else { 6 };
+            let (mut c32, mut consumed) = decode_u_escape(string, digits, start_idx)?;
+            if decoder.recombine_surrogates && (LEAD_MIN..=LEAD_MAX).contains(&c32) {
+                // only an escaped trail immediately after the lead is combined with it
+                let next = &string[consumed..];
+                if next.starts_with(decoder.escape_char) && next[1..].starts_with('u') {
+                    match decode_u_escape(next, digits, start_idx + consumed) {
+                        Ok((trail, len)) if (TRAIL_MIN..=TRAIL_MAX).contains(&trail) => {
+                            c32 = helpers::to_utf32(&[c32 as u16, trail as u16]);
+                            consumed += len;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            check_uppercase(decoder, string, consumed, start_idx)?;
+            if decoder.replace_lone_surrogates && (LEAD_MIN..=TRAIL_MAX).contains(&c32) {
+                c32 = u32::from(char::REPLACEMENT_CHARACTER);
+            }
+            match char::from_u32(c32) {
+                Some(_) if decoder.strict_u8 => push_val(pg_value!(c32)),
+                // It is a valid UTF code point. Always
+                // decode it as such.
+                Some(c) => push_val(PushGeneric::Char(c)),
+                None if decoder.reject_non_scalar => Err(DecodeError::new(
+                    DecodeErrorKind::NotAScalar,
+                    start_idx,
+                    string[..consumed].to_string(),
+                )),
+                // It is not a valid code point. Still try
+                // to record it's value "as is".
+                None => push_val(pg_value!(c32)),
+            }?;
+            consumed
+        }
+        _ => Err(DecodeError::new(
+            DecodeErrorKind::UnescapedSlash,
+            start_idx,
+            String::new(),
+        ))?,
+    };
+    // the brace form of `\u` is delimited, so it can't be ambiguous
+    let ambiguous = (bytes[1] == b'x' || bytes[1] == b'U' || bytes[1] == b'u' && bytes[2] != b'{')
+        && bytes.get(consumed_bytes).is_some_and(u8::is_ascii_hexdigit);
+    if decoder.reject_overlong_escapes && ambiguous {
+        Err(DecodeError::new(
+            DecodeErrorKind::OverlongEscape,
+            start_idx,
+            String::new(),
+        ))?
+    }
+    Ok(consumed_bytes)
+}
+
+/// Decode the single `%XX` escape at the start of `string`, returning the number of bytes
+/// consumed.
+fn decode_percent_escape<F>(
+    decoder: &super::Decoder,
+    push_val: &mut F,
+    string: &str,
+    start_idx: usize,
+) -> Result<usize, DecodeError>
+where
+    F: FnMut(PushGeneric) -> Result<(), DecodeError>,
+{
+    let bytes = string.as_bytes();
+    if bytes.len() < 3 {
+        Err(DecodeError::new(
+            DecodeErrorKind::HexNumberToShort,
+            start_idx,
+            String::new(),
+        ))?
+    }
+    match helpers::from_hex(&bytes[1..3]) {
+        Some(x) => {
+            check_uppercase(decoder, string, 3, start_idx)?;
+            push_val(PushGeneric::Value {
+                start: start_idx,
+                val: x,
+            })?
+        }
+        None => Err(DecodeError::new(
+            DecodeErrorKind::InvalidHexDigit,
+            start_idx,
+            String::new(),
+        ))?,
+    }
+    if decoder.reject_overlong_escapes && bytes.get(3).is_some_and(u8::is_ascii_hexdigit) {
+        Err(DecodeError::new(
+            DecodeErrorKind::OverlongEscape,
+            start_idx,
+            String::new(),
+        ))?
+    }
+    Ok(3)
+}
+
+/// Decode the single escape of the `entry` at the start of `string`, returning the number of bytes
+/// consumed.
+fn decode_spec_escape<F>(
+    entry: &SpecEntry,
+    push_val: &mut F,
+    string: &str,
+    start_idx: usize,
+) -> Result<usize, DecodeError>
+where
+    F: FnMut(PushGeneric) -> Result<(), DecodeError>,
+{
+    let bytes = string.as_bytes();
+    // a prefix which is a digit is also the first digit
+    let start = if (entry.prefix as char).is_digit(entry.radix) {
+        1
+    } else {
+        2
+    };
+    let is_digit = |b: &u8| (*b as char).is_digit(entry.radix);
+    let len = bytes[start..]
+        .iter()
+        .take(entry.max_digits)
+        .take_while(|b| is_digit(b))
+        .count();
+    if len == 0 {
+        let kind = if bytes.len() == start {
+            DecodeErrorKind::HexNumberToShort
+        } else {
+            DecodeErrorKind::InvalidHexDigit
         };
+        Err(DecodeError::new(kind, start_idx, String::new()))?
+    }
+    let digits = &string[start..(start + len)];
+    let val = u32::from_str_radix(digits, entry.radix).expect("the digits were checked");
+    push_val(PushGeneric::Value {
+        start: start_idx,
+        val,
+    })?;
+    Ok(start + len)
+}
 
-        string = &string[(byte_index + consumed_bytes)..];
-        offset += byte_index + consumed_bytes;
+/// Reject a lowercase hex digit in the escape `string[..end]` if the decoder requires uppercase
+/// hex.
+fn check_uppercase(
+    decoder: &super::Decoder,
+    string: &str,
+    end: usize,
+    start_idx: usize,
+) -> Result<(), DecodeError> {
+    if !decoder.require_uppercase_hex {
+        return Ok(());
+    }
+    // skip the escape character and the letter, which percent escapes don't have
+    let skip = match decoder.escape_style {
+        EscapeStyle::Backslash => 2,
+        EscapeStyle::Percent => 1,
+    };
+    let digits = &string.as_bytes()[skip..end];
+    match digits.iter().position(|b| (b'a'..=b'f').contains(b)) {
+        Some(i) => Err(DecodeError::new(
+            DecodeErrorKind::NonCanonicalHex,
+            start_idx + skip + i,
+            string[..end].to_string(),
+        )),
+        None => Ok(()),
     }
-    push_val(PushGeneric::String(string))?;
-    Ok(())
 }
 
-impl Error for DecodeError {
-    fn description(&self) -> &str {
-        match self.kind {
+/// Decode the char of the `\N{NAME}` escape at the start of `string`, returning it and the number
+/// of bytes consumed.
+#[cfg(feature = "unicode-names")]
+fn decode_name_escape(string: &str, start_idx: usize) -> Result<(char, usize), DecodeError> {
+    let unknown = || {
+        DecodeError::new(
+            DecodeErrorKind::UnknownUnicodeName,
+            start_idx,
+            String::new(),
+        )
+    };
+    let end = string.find('}').ok_or_else(unknown)?;
+    let c = unicode_names2::character(&string[3..end]).ok_or_else(unknown)?;
+    Ok((c, end + 1))
+}
+
+/// Decode the value of the `\u` escape at the start of `string`, returning it and the number of
+/// bytes consumed.
+///
+/// `digits` is the number of hex digits of the plain form, see `Decoder::short_u_escapes`.
+fn decode_u_escape(
+    string: &str,
+    digits: usize,
+    start_idx: usize,
+) -> Result<(u32, usize), DecodeError> {
+    let rest = string.len();
+    let bytes = string.as_bytes();
+    // either `\uXXXXXX` (or `\uXXXX`), the brace form `\u{X}` with 1 to 6 digits or Python's
+    // `\UXXXXXXXX`
+    let (hex, consumed) = if bytes[1] == b'U' {
+        if rest < 10 {
+            Err(DecodeError::new(
+                DecodeErrorKind::HexNumberToShort,
+                start_idx,
+                String::new(),
+            ))?
+        }
+        (&bytes[2..10], 10)
+    } else if rest > 2 && bytes[2] == b'{' {
+        match bytes[3..].iter().take(7).position(|b| *b == b'}') {
+            Some(0) => Err(DecodeError::new(
+                DecodeErrorKind::EmptyBraceEscape,
+                start_idx,
+                String::new(),
+            ))?,
+            Some(digits) => (&bytes[3..(3 + digits)], 4 + digits),
+            None if rest < 10 => Err(DecodeError::new(
+                DecodeErrorKind::HexNumberToShort,
+                start_idx,
+                String::new(),
+            ))?,
+            None => Err(DecodeError::new(
+                DecodeErrorKind::InvalidHexDigit,
+                start_idx,
+                String::new(),
+            ))?,
+        }
+    } else if rest < 2 + digits {
+        Err(DecodeError::new(
+            DecodeErrorKind::HexNumberToShort,
+            start_idx,
+            String::new(),
+        ))?
+    } else {
+        (&bytes[2..(2 + digits)], 2 + digits)
+    };
+
+    let c32 = match helpers::from_hex(hex) {
+        Some(x) if !hex.is_empty() => Ok(x),
+        _ => Err(DecodeError::new(
+            DecodeErrorKind::InvalidHexDigit,
+            start_idx,
+            String::new(),
+        )),
+    }?;
+    Ok((c32, consumed))
+}
+
+impl DecodeErrorKind {
+    fn as_str(&self) -> &'static str {
+        match *self {
             DecodeErrorKind::UnescapedSlash => r#"Found unmatched '\'. Use "\\" to escape slashes"#,
+            DecodeErrorKind::TrailingBackslash => r#"Found '\' at the end of the input"#,
             DecodeErrorKind::InvalidValue => r#"Escaped value is out of range of the decoder"#,
             DecodeErrorKind::HexNumberToShort => r#"Not enough characters after "\x" or "\u""#,
             DecodeErrorKind::InvalidHexDigit => r#"Invalid hex digit after "\x" or "\u""#,
+            DecodeErrorKind::NotUtf8 => r#"Escaped bytes are not valid UTF-8"#,
+            DecodeErrorKind::InvalidTag => r#"Missing or unknown tag"#,
+            DecodeErrorKind::NotAScalar => r#"Escaped "\u" value is not a unicode scalar value"#,
+            DecodeErrorKind::Base64 => r#"Invalid base64"#,
+            DecodeErrorKind::TooDeeplyNested => r#"Still escaped after the maximum nesting"#,
+            DecodeErrorKind::OverlongEscape => r#"Hex escape is followed by another hex digit"#,
+            DecodeErrorKind::LiteralControl => r#"Found a control character which is not escaped"#,
+            DecodeErrorKind::MixedEscapeChar => {
+                r#"Found the escape character of another dialect which is not escaped"#
+            }
+            DecodeErrorKind::InteriorNul => r#"Decoded data has an interior nul byte"#,
+            DecodeErrorKind::NonCanonicalHex => r#"Hex digit of an escape is not uppercase"#,
+            DecodeErrorKind::EmptyBraceEscape => r#"No hex digits in the braces of "\u{}""#,
+            DecodeErrorKind::UnknownUnicodeName => r#"Unknown unicode name in "\N{}""#,
         }
     }
 }
 
+impl Error for DecodeError {
+    /// Decode errors are never caused by another error.
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
 impl fmt::Display for DecodeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
             "{} when decoding {:?} [index={}]",
-            self.index, self, self.mat
+            self.kind().as_str(),
+            self.mat,
+            self.index()
         )
     }
 }
 
+/// Also shows the description of the kind, so that logged errors explain themselves.
+impl fmt::Debug for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DecodeError")
+            .field("kind", self.kind())
+            .field("index", &self.index())
+            .field("mat", &self.mat)
+            .field("line", &self.line)
+            .field("column", &self.column)
+            .field("message", &self.kind().as_str())
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod error_tests {
-    use crate::{decode::PushGeneric, DecodeError, DecodeErrorKind};
+    use crate::{decode::PushGeneric, DecodeError, DecodeErrorKind, Decoder, EscapeStyle};
 
     use super::decode_generic;
 
@@ -187,10 +1197,18 @@ mod error_tests {
                     out.extend_from_slice(s.as_bytes());
                     Ok(())
                 }
+                PushGeneric::Char(c) => {
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    Ok(())
+                }
+                PushGeneric::Bytes { bytes, .. } => {
+                    out.extend_from_slice(bytes);
+                    Ok(())
+                }
             }
         };
 
-        let result = decode_generic(f, string);
+        let result = decode_generic(&Decoder::new(), f, string);
 
         assert!(result.is_err());
         let err = result.err().unwrap();
@@ -198,6 +1216,15 @@ mod error_tests {
         assert_eq!(err_kind, err.kind);
     }
 
+    #[test]
+    fn test_error_debug() {
+        let err = crate::decode_u8(r"foo\nbar\x1").unwrap_err();
+        assert_eq!(
+            format!("{:?}", err),
+            r#"DecodeError { kind: HexNumberToShort, index: 8, mat: "\\x1", line: 1, column: 9, message: "Not enough characters after \"\\x\" or \"\\u\"" }"#
+        );
+    }
+
     #[test]
     fn test_error_unescaped_backslash() {
         do_error_test(r"foo\bar", 3, DecodeErrorKind::UnescapedSlash)
@@ -210,12 +1237,12 @@ mod error_tests {
 
     #[test]
     fn test_error_unescaped_backslash_end() {
-        do_error_test(r"foo\", 3, DecodeErrorKind::UnescapedSlash)
+        do_error_test(r"foo\", 3, DecodeErrorKind::TrailingBackslash)
     }
 
     #[test]
     fn test_error_unescaped_backslash_end_2() {
-        do_error_test(r"foo\nbar\", 8, DecodeErrorKind::UnescapedSlash);
+        do_error_test(r"foo\nbar\", 8, DecodeErrorKind::TrailingBackslash);
     }
 
     #[test]
@@ -237,4 +1264,1006 @@ mod error_tests {
     fn test_error_invalid_hex_char() {
         do_error_test(r"foo\nbar\xax", 8, DecodeErrorKind::InvalidHexDigit);
     }
+
+    #[test]
+    fn test_error_hex_multibyte() {
+        // the digits are checked as bytes, so a multibyte char is never sliced
+        do_error_test(r"foo\xé", 3, DecodeErrorKind::InvalidHexDigit);
+        do_error_test(r"é\xAé", 2, DecodeErrorKind::InvalidHexDigit);
+        do_error_test(r"foo\u0000éé", 3, DecodeErrorKind::InvalidHexDigit);
+        do_error_test(r"foo\u{é}", 3, DecodeErrorKind::InvalidHexDigit);
+
+        let decoder = Decoder {
+            x_digits: 4,
+            ..Decoder::new()
+        };
+        let err = decoder.decode_u8(r"\xFFé").unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::InvalidHexDigit, 0)
+        );
+    }
+
+    #[test]
+    fn test_error_hex_sign() {
+        do_error_test(r"foo\x+F", 3, DecodeErrorKind::InvalidHexDigit);
+        do_error_test(r"foo\u+00041", 3, DecodeErrorKind::InvalidHexDigit);
+    }
+
+    #[test]
+    fn test_error_invalid_value_matched() {
+        let decoder = Decoder::new();
+        let err = decoder.decode_u8(r"foo\u00D800bar").unwrap_err();
+        assert_eq!((&err.kind, err.index), (&DecodeErrorKind::InvalidValue, 3));
+        assert_eq!(err.matched(), r"\u00D800");
+
+        let s = "foo\n\\u{D800}bar";
+        let err = decoder.decode_u8(s).unwrap_err();
+        assert_eq!((err.index, err.matched()), (4, r"\u{D800}"));
+        assert!(err.render(s).ends_with("| ^^^^^^^^\n"));
+
+        let err = decoder.decode_u8(r"\xFF\u00DC00").unwrap_err();
+        assert_eq!((err.index, err.matched()), (4, r"\u00DC00"));
+    }
+
+    #[test]
+    fn test_error_source() {
+        use std::error::Error;
+
+        let err = decode_generic(&Decoder::new(), |_| Ok(()), r"foo\q").unwrap_err();
+        assert!(err.source().is_none());
+        assert!(err
+            .to_string()
+            .starts_with(DecodeErrorKind::UnescapedSlash.as_str()));
+    }
+
+    #[test]
+    fn test_error_matched() {
+        let err = decode_generic(&Decoder::new(), |_| Ok(()), r"foo\nbar\xax").unwrap_err();
+        assert_eq!(err.matched(), r"\xax");
+
+        let err = decode_generic(&Decoder::new(), |_| Ok(()), r"foo\u12345zbar").unwrap_err();
+        assert_eq!(err.matched(), r"\u12345z");
+
+        let err = decode_generic(&Decoder::new(), |_| Ok(()), r"foo\bar").unwrap_err();
+        assert_eq!(err.matched(), r"\b");
+    }
+
+    #[test]
+    fn test_error_recover_many() {
+        // every line has two invalid escapes, which are located without rescanning the input
+        let line = "¡ok \\\\ \\q \\xZZ\n";
+        let s = line.repeat(50_000);
+        let (out, errors) = Decoder::new().decode_u8_collect_errors(&s);
+        // compared without a diff, which is too large to print
+        assert!(out == "¡ok \\  \n".repeat(50_000).into_bytes());
+        assert_eq!(errors.len(), 100_000);
+        for (i, pair) in errors.chunks(2).enumerate() {
+            let start = i * line.len();
+            assert_eq!(
+                (
+                    &pair[0].kind,
+                    pair[0].index,
+                    pair[0].line(),
+                    pair[0].column()
+                ),
+                (&DecodeErrorKind::UnescapedSlash, start + 8, i + 1, 8)
+            );
+            assert_eq!(pair[0].matched(), r"\q");
+            assert_eq!(
+                (
+                    &pair[1].kind,
+                    pair[1].index,
+                    pair[1].line(),
+                    pair[1].column()
+                ),
+                (&DecodeErrorKind::InvalidHexDigit, start + 11, i + 1, 11)
+            );
+            assert_eq!(pair[1].matched(), r"\xZZ");
+        }
+    }
+
+    #[test]
+    fn test_error_line_column() {
+        let err = decode_generic(&Decoder::new(), |_| Ok(()), r"foo\bar").unwrap_err();
+        assert_eq!((err.line(), err.column()), (1, 4));
+
+        let err = decode_generic(&Decoder::new(), |_| Ok(()), "foo\n¡ ¢ \\b\nbar").unwrap_err();
+        assert_eq!((err.line(), err.column()), (2, 5));
+
+        // error at the end of the input
+        let err = decode_generic(&Decoder::new(), |_| Ok(()), "foo\nbar\n\\").unwrap_err();
+        assert_eq!((err.line(), err.column()), (3, 1));
+
+        let s = "foo\nbar";
+        let err = DecodeError::new(DecodeErrorKind::HexNumberToShort, s.len(), String::new());
+        let err = err.locate(s);
+        assert_eq!((err.line(), err.column()), (2, 4));
+    }
+
+    #[test]
+    fn test_error_render() {
+        let s = "foo\n\tbar \\x4G baz\n";
+        let err = decode_generic(&Decoder::new(), |_| Ok(()), s).unwrap_err();
+        let rendered = err.render(s);
+        let lines: Vec<_> = rendered.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"error: Invalid hex digit after "\x" or "\u""#,
+                " --> 2:6",
+                "  |",
+                "2 | \tbar \\x4G baz",
+                "  | \t    ^^^^",
+            ]
+        );
+        let caret = lines[4].find('^').unwrap() - "  | ".len();
+        assert_eq!(&lines[3]["2 | ".len() + caret..], r"\x4G baz");
+
+        // an error at the end of the input
+        let s = "foo\r\nbar\\";
+        let err = decode_generic(&Decoder::new(), |_| Ok(()), s).unwrap_err();
+        assert_eq!(
+            err.render(s),
+            "error: Found '\\' at the end of the input\n \
+             --> 2:4\n  |\n2 | bar\\\n  |    ^\n"
+        );
+
+        // the width of an escape depends on the decoder
+        let mut decoder = Decoder::new();
+        decoder.x_digits = 4;
+        let s = r"a \x01zz b";
+        let err = decoder.decode_u8(s).unwrap_err();
+        assert_eq!(err.matched(), r"\x01zz");
+        assert!(err.render(s).ends_with("1 | a \\x01zz b\n  |   ^^^^^^\n"));
+        let mut decoder = Decoder::new();
+        decoder.escape_style = EscapeStyle::Percent;
+        decoder.escape_char = '%';
+        let s = "a %4G b";
+        let err = decoder.decode_u8(s).unwrap_err();
+        assert_eq!(err.matched(), "%4G");
+        assert!(err.render(s).ends_with("1 | a %4G b\n  |   ^^^\n"));
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err = DecodeError::new(DecodeErrorKind::UnescapedSlash, 3, r"\bar".to_string());
+        assert_eq!(
+            err.to_string(),
+            r#"Found unmatched '\'. Use "\\" to escape slashes when decoding "\\bar" [index=3]"#
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        decode::PushGeneric, encode_u8, DecodeError, DecodeErrorKind, Decoder, Encoder, EscapeSpec,
+        EscapeStyle, SpecEntry,
+    };
+
+    use super::decode_generic;
+
+    fn decode(decoder: &Decoder, s: &str) -> Result<Vec<u8>, DecodeError> {
+        let mut out: Vec<u8> = Vec::new();
+        let f = |val: PushGeneric| -> Result<(), DecodeError> {
+            match val {
+                PushGeneric::Value { val, start: _ } => out.push(val as u8),
+                PushGeneric::String(s) => out.extend_from_slice(s.as_bytes()),
+                PushGeneric::Char(c) => {
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                }
+                PushGeneric::Bytes { bytes, .. } => out.extend_from_slice(bytes),
+            }
+            Ok(())
+        };
+        decode_generic(decoder, f, s)?;
+        Ok(out)
+    }
+
+    fn assert_round_x_digits(x_digits: usize, v: &[u8]) {
+        let mut encoder = Encoder::new();
+        encoder.x_digits = x_digits;
+        let mut decoder = Decoder::new();
+        decoder.x_digits = x_digits;
+
+        let encoded = encode_u8::encode(&encoder, v);
+        assert_eq!(v, decode(&decoder, &encoded).unwrap().as_slice());
+    }
+
+    #[test]
+    fn sanity_x_digits_roundtrip() {
+        for &x_digits in &[2, 4] {
+            assert_round_x_digits(x_digits, b"");
+            assert_round_x_digits(x_digits, b"foo");
+            assert_round_x_digits(x_digits, b"\xFF");
+            assert_round_x_digits(x_digits, b"foo\x01bar\n\\");
+            assert_round_x_digits(x_digits, b"\x01\x02\x03");
+            assert_round_x_digits(x_digits, b"\x01\\\x02\n\x03");
+
+            let mut bytes: Vec<u8> = Vec::new();
+            bytes.extend_from_slice("\x01¡ ¢".as_bytes());
+            bytes.extend_from_slice(b"\xFE");
+            bytes.extend_from_slice("£".as_bytes());
+            assert_round_x_digits(x_digits, &bytes);
+        }
+    }
+
+    #[test]
+    fn sanity_reject_non_scalar() {
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            decode(&decoder, r"\u10FFFF").unwrap(),
+            "\u{10FFFF}".as_bytes()
+        );
+        // pushed as a value (which doesn't fit in a `u8`)
+        assert!(decode(&decoder, r"\u110000").is_ok());
+
+        decoder.reject_non_scalar = true;
+        assert_eq!(
+            decode(&decoder, r"\u10FFFF").unwrap(),
+            "\u{10FFFF}".as_bytes()
+        );
+        for s in &[r"foo\u110000", r"foo\uFFFFFF", r"foo\u00D800"] {
+            let err = decode(&decoder, s).unwrap_err();
+            assert_eq!(err.matched(), &s[3..]);
+            assert_eq!((err.kind, err.index), (DecodeErrorKind::NotAScalar, 3));
+        }
+    }
+
+    #[test]
+    fn sanity_escape_runs() {
+        let mut pushes: Vec<String> = Vec::new();
+        let f = |val: PushGeneric| -> Result<(), DecodeError> {
+            pushes.push(match val {
+                PushGeneric::Value { val, start } => format!("value {} {}", val, start),
+                PushGeneric::String(s) => format!("string {:?}", s),
+                PushGeneric::Char(c) => format!("char {:?}", c),
+                PushGeneric::Bytes { starts, bytes } => format!("bytes {:?} {:?}", bytes, starts),
+            });
+            Ok(())
+        };
+        decode_generic(&Decoder::new(), f, r"\x01\x02\n\\a\x03\u000041\x04\u00D800").unwrap();
+        assert_eq!(
+            pushes,
+            vec![
+                "bytes [1, 2, 10, 92] [0, 4, 8, 10]",
+                "string \"a\"",
+                "bytes [3] [13]",
+                "char 'A'",
+                "bytes [4] [25]",
+                "value 55296 29",
+                "string \"\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn sanity_case_insensitive_shorthands() {
+        let mut decoder = Decoder::new();
+        for s in &[r"\T", r"\N", r"\R"] {
+            let err = decode(&decoder, s).unwrap_err();
+            assert_eq!(err.kind, DecodeErrorKind::UnescapedSlash);
+        }
+
+        decoder.case_insensitive_shorthands = true;
+        assert_eq!(
+            decode(&decoder, r"a\Tb\Nc\Rd\t\n\r").unwrap(),
+            b"a\tb\nc\rd\t\n\r"
+        );
+        assert!(decode(&decoder, r"\X41").is_err());
+    }
+
+    #[test]
+    fn sanity_decode_nested() {
+        let mut decoder = Decoder::new();
+        decoder.max_nesting = 4;
+
+        // `\n` encoded 6 times
+        let mut s = "\n".to_string();
+        for _ in 0..6 {
+            s = encode_u8::encode(&Encoder::new(), s.as_bytes());
+        }
+        assert_eq!(s, format!("{}n", "\\".repeat(32)));
+
+        let err = decoder.decode_u8_nested(&s, 6).unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::TooDeeplyNested);
+        assert_eq!(err.matched(), r"\\");
+        let err = decoder
+            .decode_u8_nested(&format!("foo\n  {}", s), 6)
+            .unwrap_err();
+        assert_eq!((err.index, err.line(), err.column()), (6, 2, 3));
+        let err = decoder.decode_u8_nested(&s, usize::MAX).unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::TooDeeplyNested);
+
+        assert_eq!(decoder.decode_u8_nested(&s, 4).unwrap(), br"\\n");
+        decoder.max_nesting = 6;
+        assert_eq!(decoder.decode_u8_nested(&s, 6).unwrap(), b"\n");
+        // stops once there is nothing left to decode
+        assert_eq!(decoder.decode_u8_nested(&s, usize::MAX).unwrap(), b"\n");
+        assert_eq!(decoder.decode_u8_nested(&s, 0).unwrap(), s.as_bytes());
+
+        assert_eq!(
+            decoder.decode_u8_nested(r"\\xFF\\x41", 2).unwrap(),
+            b"\xFFA"
+        );
+        let err = decoder.decode_u8_nested(r"\xFF\\x41", 2).unwrap_err();
+        assert_eq!((&err.kind, err.index), (&DecodeErrorKind::NotUtf8, 0));
+        assert_eq!(err.matched(), r"\xFF");
+        // the invalid byte is located at its escape
+        let err = decoder.decode_u8_nested("a\n\\xC2 \\\\x41", 2).unwrap_err();
+        assert_eq!((&err.kind, err.index), (&DecodeErrorKind::NotUtf8, 2));
+        assert_eq!((err.line(), err.column(), err.matched()), (2, 1, r"\xC2"));
+    }
+
+    #[test]
+    fn sanity_recombine_surrogates() {
+        let mut decoder = Decoder::new();
+        let s = r"\u00D83D\u00DE00";
+        assert!(decoder.decode_u8(s).is_err());
+        assert_eq!(decoder.decode_u16(s).unwrap(), vec![0xD83D, 0xDE00]);
+
+        decoder.recombine_surrogates = true;
+        assert_eq!(decoder.decode_u8(s).unwrap(), "\u{1F600}".as_bytes());
+        assert_eq!(
+            decoder.decode_u8(r"a\u{D83D}\u00DE00b").unwrap(),
+            "a\u{1F600}b".as_bytes()
+        );
+        assert_eq!(decoder.decode_u16(s).unwrap(), vec![0xD83D, 0xDE00]);
+
+        // lone or out of order surrogates are left alone
+        for s in &[
+            r"\u00D83D",
+            r"\u00DE00\u00D83D",
+            r"\u00D83D \u00DE00",
+            r"\u00D83D\u00D83D",
+            r"\u00D83D\x41",
+        ] {
+            assert!(decoder.decode_u8(s).is_err(), "{}", s);
+            let expected = Decoder::new().decode_u16(s).unwrap();
+            assert_eq!(decoder.decode_u16(s).unwrap(), expected, "{}", s);
+        }
+        decoder.reject_non_scalar = true;
+        assert!(decoder.decode_u8(s).is_ok());
+        let err = decoder.decode_u8(r"\u00D83D\u00D83D").unwrap_err();
+        assert_eq!(
+            (&err.kind, err.matched()),
+            (&DecodeErrorKind::NotAScalar, r"\u00D83D")
+        );
+    }
+
+    #[test]
+    fn sanity_replace_lone_surrogates() {
+        let mut decoder = Decoder::new();
+        decoder.replace_lone_surrogates = true;
+        let lead = r"a\u00D83Db";
+        let trail = r"a\u{DE00}b";
+        let pair = r"a\u00D83D\u00DE00b";
+        assert_eq!(decoder.decode_u16(lead).unwrap(), [0x61, 0xFFFD, 0x62]);
+        assert_eq!(decoder.decode_u16(trail).unwrap(), [0x61, 0xFFFD, 0x62]);
+        assert_eq!(decoder.decode_u8(lead).unwrap(), "a\u{FFFD}b".as_bytes());
+        // the pair is only kept if it is recombined
+        assert_eq!(
+            decoder.decode_u16(pair).unwrap(),
+            [0x61, 0xFFFD, 0xFFFD, 0x62]
+        );
+
+        decoder.recombine_surrogates = true;
+        assert_eq!(
+            decoder.decode_u16(pair).unwrap(),
+            [0x61, 0xD83D, 0xDE00, 0x62]
+        );
+        assert_eq!(decoder.decode_u8(pair).unwrap(), "a\u{1F600}b".as_bytes());
+        assert_eq!(
+            decoder.decode_u16(r"\u00DE00\u00D83D").unwrap(),
+            [0xFFFD, 0xFFFD]
+        );
+
+        // values which are not UTF-16 at all are still errors
+        let err = decoder.decode_u16(r"\u110000").unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn sanity_decode_into() {
+        let mut items = Vec::new();
+        super::decode_into(&Decoder::new(), r"ab\x41\n\u00D800¡\u0000A1", |item| {
+            items.push(format!("{:?}", item));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            items,
+            [
+                r#"Str("ab")"#,
+                "Value { index: 2, value: 65 }",
+                "Value { index: 6, value: 10 }",
+                "Value { index: 8, value: 55296 }",
+                r#"Str("¡")"#,
+                "Char('¡')",
+            ]
+        );
+
+        let err = super::decode_into(&Decoder::new(), "foo\nbar\\x41", |item| match item {
+            super::DecodedItem::Value { index, .. } => Err(DecodeError::invalid_value(index)),
+            _ => Ok(()),
+        })
+        .unwrap_err();
+        assert_eq!((&err.kind, err.index), (&DecodeErrorKind::InvalidValue, 7));
+        assert_eq!((err.line(), err.column(), err.matched()), (2, 4, r"\x41"));
+    }
+
+    #[test]
+    fn sanity_collect_errors() {
+        let decoder = Decoder::new();
+        let s = "\u{FEFF}ok\\n\\u00D800 ¡\\q\n\\x4\\x41\\u{}\\";
+        let (decoded, errors) = decoder.decode_u8_collect_errors(s);
+        assert_eq!(decoded, "\u{FEFF}ok\n ¡\nA".as_bytes());
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| (e.kind.clone(), e.index, e.line(), e.column()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (DecodeErrorKind::InvalidValue, 7, 1, 6),
+                (DecodeErrorKind::UnescapedSlash, 18, 1, 16),
+                (DecodeErrorKind::InvalidHexDigit, 21, 2, 1),
+                (DecodeErrorKind::EmptyBraceEscape, 28, 2, 8),
+                (DecodeErrorKind::TrailingBackslash, 32, 2, 12),
+            ]
+        );
+        assert_eq!(errors[0].matched(), r"\u00D800");
+        assert_eq!(errors[3].matched(), r"\u{}");
+
+        // the same errors are found after a BOM is stripped
+        let mut decoder = Decoder::new();
+        decoder.strip_bom = true;
+        let (decoded, bom_errors) = decoder.decode_u8_collect_errors(s);
+        assert_eq!(decoded, "ok\n ¡\nA".as_bytes());
+        assert_eq!(bom_errors, errors);
+
+        let (decoded, errors) = decoder.decode_u8_collect_errors(r"foo\n");
+        assert_eq!((decoded, errors), (b"foo\n".to_vec(), vec![]));
+
+        // the whole of a long `\x` escape is skipped, keeping the bytes before the error
+        let mut decoder = Decoder::new();
+        decoder.x_digits = 4;
+        let (decoded, errors) = decoder.decode_u8_collect_errors(r"a\x01zzb");
+        assert_eq!(decoded, b"a\x01b");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            (&errors[0].kind, errors[0].index, errors[0].matched()),
+            (&DecodeErrorKind::InvalidHexDigit, 1, r"\x01zz")
+        );
+    }
+
+    #[test]
+    fn sanity_python_u_escape() {
+        let decoder = Decoder::new();
+        assert_eq!(
+            decoder.decode_u8(r"a\U0001F600b\U00000041").unwrap(),
+            "a\u{1F600}bA".as_bytes()
+        );
+        assert_eq!(decoder.decode_u16(r"\U0000D800").unwrap(), [0xD800]);
+        assert_eq!(decoder.decode_u16(r"\U0001F600").unwrap(), [0xD83D, 0xDE00]);
+
+        let err = decoder.decode_u8(r"\U0001F60").unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::HexNumberToShort);
+        let err = decoder.decode_u8(r"\U{1F600}a").unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::InvalidHexDigit);
+        let err = decoder.decode_u8(r"ab\UFFFFFFFFcd").unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index, err.matched()),
+            (&DecodeErrorKind::InvalidValue, 2, r"\UFFFFFFFF")
+        );
+        let err = decoder.decode_u16(r"\U00110000").unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn sanity_reject_overlong_escapes() {
+        let mut decoder = Decoder::new();
+        let s = r"foo\x411";
+        assert_eq!(decoder.decode_u8(s).unwrap(), b"fooA1");
+
+        decoder.reject_overlong_escapes = true;
+        for &(s, index) in &[(r"foo\x411", 3), (r"\xFFa", 0), (r"\n\u0000410", 2)] {
+            let err = decoder.decode_u8(s).unwrap_err();
+            assert_eq!(
+                (&err.kind, err.index),
+                (&DecodeErrorKind::OverlongEscape, index),
+                "{}",
+                s
+            );
+        }
+        assert_eq!(decoder.decode_u8(r"\x41g\x41").unwrap(), b"AgA");
+        assert_eq!(decoder.decode_u8(r"\u{41}1\n1\\1").unwrap(), b"A1\n1\\1");
+
+        decoder.x_digits = 4;
+        assert_eq!(decoder.decode_u8(r"\x4142 \x41").unwrap(), b"AB A");
+        let err = decoder.decode_u8(r"\x41421").unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::OverlongEscape);
+    }
+
+    #[test]
+    fn sanity_reject_literal_controls() {
+        let mut decoder = Decoder::new();
+        let s = "red:\x1B[31m \u{7}\t\r\n";
+        assert_eq!(decoder.decode_u8(s).unwrap(), s.as_bytes());
+
+        decoder.reject_literal_controls = true;
+        let err = decoder.decode_u8(s).unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::LiteralControl, 4)
+        );
+        assert_eq!(
+            err.render(s),
+            "error: Found a control character which is not escaped\n --> 1:5\n  |\n\
+             1 | red:\x1B[31m \u{7}\t\n  |     ^\n"
+        );
+        // escaped controls and whitespace are fine
+        let s = "\tred:\\x1B[31m\r\n\\x00\\u{7}\\\\";
+        assert_eq!(
+            decoder.decode_u8(s).unwrap(),
+            b"\tred:\x1B[31m\r\n\x00\x07\\"
+        );
+
+        let (decoded, errors) = decoder.decode_u8_collect_errors("a\x00b\\nc\x1Fd");
+        assert_eq!(decoded, b"ab\ncd");
+        let found: Vec<_> = errors.iter().map(|e| (&e.kind, e.index)).collect();
+        assert_eq!(
+            found,
+            [
+                (&DecodeErrorKind::LiteralControl, 1),
+                (&DecodeErrorKind::LiteralControl, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn sanity_strict_escape_char() {
+        let mut decoder = Decoder::new();
+        decoder.escape_char = '%';
+        let s = r"red:\x1B[31m%x41";
+        assert_eq!(decode(&decoder, s).unwrap(), b"red:\\x1B[31mA");
+
+        decoder.strict_escape_char = true;
+        let err = decoder.decode_u8(s).unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::MixedEscapeChar, 4)
+        );
+        assert_eq!(
+            err.render(s),
+            "error: Found the escape character of another dialect which is not escaped\n --> 1:5\n  |\n\
+             1 | red:\\x1B[31m%x41\n  |     ^\n"
+        );
+        assert_eq!(decode(&decoder, "50%% off%x0A").unwrap(), b"50% off\n");
+
+        // with `\` escapes the other dialect is percent-encoding
+        let mut decoder = Decoder::new();
+        decoder.strict_escape_char = true;
+        decoder.reject_literal_controls = true;
+        assert_eq!(decode(&decoder, r"\\x1B\x25").unwrap(), b"\\x1B%");
+        let (decoded, errors) = decoder.decode_u8_collect_errors("a%1B\x00b\\x25");
+        assert_eq!(decoded, b"a1Bb%");
+        let found: Vec<_> = errors.iter().map(|e| (&e.kind, e.index)).collect();
+        assert_eq!(
+            found,
+            [
+                (&DecodeErrorKind::MixedEscapeChar, 1),
+                (&DecodeErrorKind::LiteralControl, 4),
+            ]
+        );
+        let mut buf = b"ok\\n100%".to_vec();
+        let err = decoder.decode_u8_in_place(&mut buf).unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::MixedEscapeChar, 7)
+        );
+    }
+
+    #[test]
+    fn sanity_require_uppercase_hex() {
+        let mut decoder = Decoder::new();
+        let s = r"ab\xfF\u{1f600}";
+        assert_eq!(decoder.decode_u8(s).unwrap(), b"ab\xFF\xF0\x9F\x98\x80");
+
+        decoder.require_uppercase_hex = true;
+        for &(s, start, index) in &[
+            (r"ab\xfF", 2, 4),
+            (r"ab\xFf", 2, 5),
+            (r"\u{1F60a}", 0, 7),
+            (r"\n\u00d800", 2, 6),
+            (r"\U0001f600", 0, 6),
+        ] {
+            let err = decoder.decode_u8(s).unwrap_err();
+            assert_eq!(
+                (&err.kind, err.index),
+                (&DecodeErrorKind::NonCanonicalHex, index),
+                "{}",
+                s
+            );
+            assert_eq!(err.matched(), &s[start..]);
+            // the prefix stops at the start of the escape, not at the digit
+            let prefix = decoder.decode_u8(&s[..start]).unwrap();
+            assert_eq!(decoder.decode_u8_prefix(s), (prefix, start));
+        }
+        let s = r"\xFF\u{1F600}\u00D800\n fab";
+        assert_eq!(
+            decoder.decode_u16(s).unwrap(),
+            Decoder::new().decode_u16(s).unwrap()
+        );
+
+        decoder.recombine_surrogates = true;
+        let s = r"\u00D83D\u00DE00";
+        assert_eq!(decoder.decode_u8(s).unwrap(), "\u{1F600}".as_bytes());
+        let err = decoder.decode_u8(r"\u00D83D\u00de00").unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::NonCanonicalHex, 12)
+        );
+        // the lead is not valid without its trail
+        assert_eq!(decoder.decode_u8_prefix(r"\u00D83D\u00de00"), (vec![], 0));
+    }
+
+    #[test]
+    fn sanity_decode_in_place() {
+        let mut decoder = Decoder::new();
+        decoder.x_digits = 4;
+        decoder.recombine_surrogates = true;
+        decoder.strip_bom = true;
+        decoder.allow_line_continuation = true;
+        let s = "\u{FEFF}¡\\xFFFE\\\n\\u00D83D\\u00DE00\\t\\u{41}\\U0001F600 \\xFF";
+        let mut buf = s.as_bytes().to_vec();
+        super::decode_u8_in_place(&decoder, &mut buf).unwrap();
+        assert_eq!(buf, decoder.decode_u8(s).unwrap());
+        assert_eq!(
+            buf,
+            &b"\xC2\xA1\xFF\xFE\xF0\x9F\x98\x80\tA\xF0\x9F\x98\x80 \xFF"[..]
+        );
+
+        // the buffer is unchanged on errors
+        for s in &[
+            &b"foo\\xFFFF\\u00D800"[..],
+            b"foo\\xFFFF\\q",
+            b"foo\\xFFFF\xFF",
+        ] {
+            let mut buf = s.to_vec();
+            let err = super::decode_u8_in_place(&decoder, &mut buf).unwrap_err();
+            assert_eq!(&buf, s);
+            assert_eq!((err.index, err.line(), err.column()), (9, 1, 10));
+        }
+    }
+
+    #[test]
+    fn sanity_normalize_crlf() {
+        let mut decoder = Decoder::new();
+        let s = "foo\r\nbar\\r\\n\r\r\n\\x0D\n\r\n";
+        assert_eq!(
+            decode(&decoder, s).unwrap(),
+            b"foo\r\nbar\r\n\r\r\n\r\n\r\n"
+        );
+
+        decoder.normalize_crlf = true;
+        let expected = b"foo\nbar\r\n\r\n\r\n\n";
+        assert_eq!(decode(&decoder, s).unwrap(), expected);
+        let mut buf = s.as_bytes().to_vec();
+        decoder.decode_u8_in_place(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        decoder.reject_literal_controls = true;
+        assert_eq!(decode(&decoder, s).unwrap(), expected);
+        let err = decode(&decoder, "a\r\nb\x1Bc").unwrap_err();
+        assert_eq!((err.kind, err.index), (DecodeErrorKind::LiteralControl, 4));
+    }
+
+    #[test]
+    fn sanity_line_continuation() {
+        let mut decoder = Decoder::new();
+        let s = "foo\\\nbar\\\r\n\\xFF\\\n";
+        let err = decoder.decode_u8(s).unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::UnescapedSlash, 3)
+        );
+
+        decoder.allow_line_continuation = true;
+        assert_eq!(decoder.decode_u8(s).unwrap(), b"foobar\xFF");
+        assert_eq!(
+            decoder.decode_u16(s).unwrap(),
+            decoder.decode_u16("foobar\\xFF").unwrap()
+        );
+        // a lone cariage return is not a continuation
+        let err = decoder.decode_u8("foo\\\rbar").unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::UnescapedSlash, 3)
+        );
+
+        // errors after continuations are still located in the input
+        let err = decoder.decode_u8("foo\\\r\nbar\\\nba\\q").unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::UnescapedSlash, 13)
+        );
+        assert_eq!((err.line(), err.column(), err.matched()), (3, 3, "\\q"));
+    }
+
+    #[test]
+    fn sanity_strip_bom() {
+        let mut decoder = Decoder::new();
+        let s = "\u{FEFF}foo\\xFF\\u{FEFF}";
+        assert_eq!(
+            decoder.decode_u8(s).unwrap(),
+            b"\xEF\xBB\xBFfoo\xFF\xEF\xBB\xBF"
+        );
+
+        decoder.strip_bom = true;
+        // only a leading BOM is removed
+        assert_eq!(decoder.decode_u8(s).unwrap(), b"foo\xFF\xEF\xBB\xBF");
+        assert_eq!(
+            decoder.decode_u8("foo\u{FEFF}").unwrap(),
+            "foo\u{FEFF}".as_bytes()
+        );
+        assert_eq!(
+            decoder.decode_u16("\u{FEFF}\u{FEFF}").unwrap(),
+            vec![0xFEFF]
+        );
+        assert!(decoder.decode_u8("\u{FEFF}").unwrap().is_empty());
+
+        let err = decoder.decode_u8("\u{FEFF}foo\\q").unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::UnescapedSlash, 6)
+        );
+        assert_eq!((err.line(), err.column()), (1, 5));
+    }
+
+    #[test]
+    fn sanity_strict_u8() {
+        let mut decoder = Decoder::new();
+        let s = r"foo\u000156";
+        assert_eq!(decoder.decode_u8(s).unwrap(), "fooŖ".as_bytes());
+
+        decoder.strict_u8 = true;
+        let err = decoder.decode_u8(s).unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index, err.matched()),
+            (&DecodeErrorKind::InvalidValue, 3, r"\u000156")
+        );
+        assert_eq!(
+            decoder.decode_u8(r"\u000041\u{FF}\x7F").unwrap(),
+            b"A\xFF\x7F"
+        );
+        assert_eq!(
+            decoder.decode_u16(s).unwrap(),
+            Decoder::new().decode_u16(s).unwrap()
+        );
+        assert!(decoder.decode_u16(r"\u01F600").is_err());
+        assert_eq!(decoder.decode_u8(r"foo\nbar").unwrap(), b"foo\nbar");
+    }
+
+    #[test]
+    fn sanity_null_escape() {
+        let decoder = Decoder::new();
+        assert_eq!(
+            decode(&decoder, r"foo\0\x00\00").unwrap(),
+            b"foo\x00\x00\x000"
+        );
+
+        let bytes = b"\x00foo\x00\x000\n\x00";
+        for &encode_null in &[false, true] {
+            let mut encoder = Encoder::new();
+            encoder.encode_null = encode_null;
+            let encoded = encode_u8::encode(&encoder, bytes);
+            assert_eq!(encoded.contains(r"\0"), encode_null);
+            assert_eq!(decode(&decoder, &encoded).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn sanity_brace_escape() {
+        let decoder = Decoder::new();
+        assert_eq!(
+            decode(&decoder, r"\u{1F600} \u{a2}\u{41}\u{00041}").unwrap(),
+            "\u{1F600} \u{A2}AA".as_bytes()
+        );
+        assert_eq!(
+            decoder.decode_u16(r"\u{1f600}\u{D800}").unwrap(),
+            vec![0xD83D, 0xDE00, 0xD800]
+        );
+
+        for (s, kind) in &[
+            (r"\u{1F600", DecodeErrorKind::HexNumberToShort),
+            (r"\u{", DecodeErrorKind::HexNumberToShort),
+            (r"\u{1F600G}", DecodeErrorKind::InvalidHexDigit),
+            (r"\u{0000041}", DecodeErrorKind::InvalidHexDigit),
+            (r"\u{}", DecodeErrorKind::EmptyBraceEscape),
+            (r"\u{}1", DecodeErrorKind::EmptyBraceEscape),
+        ] {
+            let err = decode(&decoder, s).unwrap_err();
+            assert_eq!((&err.kind, err.index), (kind, 0), "{}", s);
+        }
+    }
+
+    #[cfg(feature = "unicode-names")]
+    #[test]
+    fn sanity_name_escape() {
+        let decoder = Decoder::new();
+        assert_eq!(
+            decode(&decoder, r"\N{GREEK SMALL LETTER ALPHA}+\N{grinning face}").unwrap(),
+            "\u{3B1}+\u{1F600}".as_bytes()
+        );
+        assert_eq!(
+            decoder.decode_u16(r"\N{LATIN CAPITAL LETTER A}").unwrap(),
+            vec![0x41]
+        );
+        let mut buf = br"a\N{GREEK SMALL LETTER ALPHA}b".to_vec();
+        decoder.decode_u8_in_place(&mut buf).unwrap();
+        assert_eq!(buf, "a\u{3B1}b".as_bytes());
+
+        for s in &[
+            r"\N{NOT A CHARACTER}",
+            r"\N{GREEK SMALL LETTER ALPHA",
+            r"\N{}",
+        ] {
+            let err = decode(&decoder, s).unwrap_err();
+            assert_eq!(
+                (&err.kind, err.index),
+                (&DecodeErrorKind::UnknownUnicodeName, 0)
+            );
+        }
+        let input = r"a \N{NOPE} b";
+        let err = decode(&decoder, input).unwrap_err();
+        assert!(err.render(input).ends_with("  |   ^^^^^^^^\n"));
+    }
+
+    #[test]
+    fn sanity_escape_char() {
+        let mut encoder = Encoder::new();
+        encoder.escape_char = '%';
+        let mut decoder = Decoder::new();
+        decoder.escape_char = '%';
+
+        assert_eq!(
+            decode(&decoder, r"C:\foo%%%n%xFF%u000041").unwrap(),
+            b"C:\\foo%\n\xFFA"
+        );
+        let err = decode(&decoder, r"foo%bar").unwrap_err();
+        assert_eq!(err.index, 3);
+
+        let bytes = b"C:\\foo%\n\xFF\t";
+        let encoded = encode_u8::encode(&encoder, bytes);
+        assert_eq!(decode(&decoder, &encoded).unwrap(), bytes);
+    }
+
+    #[test]
+    fn sanity_percent_style() {
+        let mut decoder = Decoder::new();
+        decoder.escape_style = EscapeStyle::Percent;
+        decoder.escape_char = '%';
+        assert_eq!(
+            decode(&decoder, r"C:\foo 100%25%09%0a%FF").unwrap(),
+            b"C:\\foo 100%\t\n\xFF"
+        );
+        assert_eq!(decoder.decode_u16("%41%FF").unwrap(), vec![0x41, 0xFF]);
+
+        for (s, kind, index) in &[
+            ("100%", DecodeErrorKind::TrailingBackslash, 3),
+            ("%4", DecodeErrorKind::HexNumberToShort, 0),
+            ("a%%", DecodeErrorKind::HexNumberToShort, 1),
+            ("%xFF", DecodeErrorKind::InvalidHexDigit, 0),
+            ("%u000041", DecodeErrorKind::InvalidHexDigit, 0),
+        ] {
+            let err = decode(&decoder, s).unwrap_err();
+            assert_eq!((&err.kind, err.index), (kind, *index), "{}", s);
+        }
+
+        decoder.require_uppercase_hex = true;
+        let err = decode(&decoder, "%0a").unwrap_err();
+        assert_eq!((err.kind, err.index), (DecodeErrorKind::NonCanonicalHex, 2));
+        decoder.reject_overlong_escapes = true;
+        let err = decode(&decoder, "%411").unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::OverlongEscape);
+
+        let encoder = Encoder::new().with_escape_style(EscapeStyle::Percent);
+        let bytes = b"C:\\foo%\n\xFF\t\x00%%";
+        let encoded = encode_u8::encode(&encoder, bytes);
+        assert_eq!(decode(&Decoder::new(), &encoded).unwrap_err().index, 2);
+        assert_eq!(decode(&decoder, &encoded).unwrap(), bytes);
+    }
+
+    #[test]
+    fn sanity_escape_spec() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.escape_spec, EscapeSpec::default());
+        assert_eq!(decode(&decoder, r"\033").unwrap(), b"\x0033");
+
+        decoder.escape_spec = EscapeSpec::with_octal();
+        assert_eq!(
+            decode(&decoder, r"\033[0m\0\08\1011\7\\\x41").unwrap(),
+            b"\x1B[0m\x00\x008A1\x07\\A"
+        );
+        assert_eq!(decoder.decode_u16(r"\777").unwrap(), vec![0o777]);
+        let err = decoder.decode_u8(r"a\777").unwrap_err();
+        assert_eq!((err.kind, err.index), (DecodeErrorKind::InvalidValue, 1));
+
+        const ENTRIES: &[SpecEntry] = &[SpecEntry {
+            prefix: b'o',
+            radix: 8,
+            max_digits: 3,
+        }];
+        decoder.escape_spec = EscapeSpec {
+            entries: ENTRIES,
+            ..EscapeSpec::stfu8()
+        };
+        assert_eq!(decode(&decoder, r"\o101\o7\0").unwrap(), b"A\x07\x00");
+        for (s, kind) in &[
+            (r"\o", DecodeErrorKind::HexNumberToShort),
+            (r"\o8", DecodeErrorKind::InvalidHexDigit),
+        ] {
+            let err = decode(&decoder, s).unwrap_err();
+            assert_eq!((&err.kind, err.index), (kind, 0), "{}", s);
+        }
+    }
+
+    #[test]
+    fn sanity_dialect_presets() {
+        let bytes = "\\'\"\t\n\x07\x1B\x7F\u{e9}\u{1F600}\u{FFFF} 0".as_bytes();
+        let mut v16: Vec<u16> = "a\u{1F600}".encode_utf16().collect();
+        v16.extend_from_slice(&[0xD800, b'0' as u16]);
+        for (encoder, decoder, expected) in &[
+            (
+                Encoder::python(),
+                Decoder::python(),
+                "\\\\'\"\\t\\n\\x07\\x1B\\x7F\u{e9}\\U0001F600\u{FFFF} 0",
+            ),
+            (
+                Encoder::rust(),
+                Decoder::rust(),
+                "\\\\'\"\\t\\n\\x07\\x1B\\x7F\u{e9}\\u{1F600}\u{FFFF} 0",
+            ),
+            (
+                Encoder::shell(),
+                Decoder::shell(),
+                "\\\\'\"\\t\\n\\x07\\x1B\\x7F\u{e9}\u{1F600}\u{FFFF} 0",
+            ),
+        ] {
+            let encoded = encoder.encode_u8(bytes);
+            assert_eq!(&encoded, expected);
+            assert_eq!(decoder.decode_u8(&encoded).unwrap(), bytes);
+            assert_eq!(decoder.decode_u16(&encoder.encode_u16(&v16)).unwrap(), v16);
+        }
+
+        for decoder in &[Decoder::python(), Decoder::rust(), Decoder::shell()] {
+            assert_eq!(decode(decoder, r#"\'\"\\"#).unwrap(), b"'\"\\");
+        }
+        let python = Decoder::python();
+        assert_eq!(
+            decode(&python, "\\a\\b\\f\\v\\0\\12\\\n!").unwrap(),
+            b"\x07\x08\x0C\x0B\x00\n!"
+        );
+        assert_eq!(decode(&python, r"\u00411").unwrap(), b"A1");
+        let err = decode(&python, r"\e").unwrap_err();
+        assert_eq!((err.kind, err.index), (DecodeErrorKind::UnescapedSlash, 0));
+        assert_eq!(decode(&Decoder::shell(), r"\e[0m").unwrap(), b"\x1B[0m");
+        let err = decode(&Decoder::rust(), r"\a").unwrap_err();
+        assert_eq!((err.kind, err.index), (DecodeErrorKind::UnescapedSlash, 0));
+    }
+
+    #[test]
+    fn sanity_x_digits_decode() {
+        let mut decoder = Decoder::new();
+        decoder.x_digits = 4;
+        assert_eq!(
+            decode(&decoder, r"foo\x00FFbar").unwrap(),
+            b"foo\x00\xFFbar"
+        );
+        assert_eq!(decode(&decoder, r"foo\xFF").unwrap(), b"foo\xFF");
+        assert!(decode(&decoder, r"foo\xFFzz").is_err());
+        assert!(decode(&decoder, r"foo\xFFF").is_err());
+    }
 }
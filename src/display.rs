@@ -0,0 +1,73 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+use std::fmt;
+
+use crate::encode_u8;
+
+/// Display bytes as STFU-8 without allocating an intermediate `String`.
+///
+/// The output is identical to [`encode_u8`](fn.encode_u8.html).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let buf = b"foo\xFF\nbar";
+/// assert_eq!(
+///     format!("{}", stfu8::Stfu8(buf)),
+///     r"foo\xFF\nbar"
+/// );
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Stfu8<'a>(pub &'a [u8]);
+
+/// Display bytes as "pretty" STFU-8 without allocating an intermediate `String`.
+///
+/// The output is identical to [`encode_u8_pretty`](fn.encode_u8_pretty.html).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let buf = b"foo\xFF\nbar";
+/// assert_eq!(
+///     format!("{}", stfu8::Stfu8Pretty(buf)),
+///     "foo\\xFF\nbar"
+/// );
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Stfu8Pretty<'a>(pub &'a [u8]);
+
+impl<'a> fmt::Display for Stfu8<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        encode_u8::encode_fmt(&super::Encoder::new(), self.0, f)
+    }
+}
+
+impl<'a> fmt::Display for Stfu8Pretty<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        encode_u8::encode_fmt(&super::Encoder::pretty(), self.0, f)
+    }
+}
+
+#[test]
+fn sanity_display() {
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice("¡ ¢ £\\".as_bytes());
+    bytes.extend_from_slice(b"\t\n\r\x07\x7f\xFE");
+    assert_eq!(format!("{}", Stfu8(&bytes)), super::encode_u8(&bytes));
+    assert_eq!(
+        format!("{}", Stfu8Pretty(&bytes)),
+        super::encode_u8_pretty(&bytes)
+    );
+}
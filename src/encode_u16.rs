@@ -10,9 +10,9 @@
 //! `run_utf8_validation` function, used by `str::from_utf8`.
 
 use std::char;
-use std::u16;
+use std::fmt;
 
-use helpers;
+use crate::helpers;
 
 /*
 Section: UTF-8 validation
@@ -23,48 +23,114 @@ const LEAD_MAX: u16 = 0xDBFF;
 const TRAIL_MIN: u16 = 0xDC00;
 const TRAIL_MAX: u16 = 0xDFFF;
 
+/// Encode UTF-16 bytes, which are paired into units with `from_bytes`.
+///
+/// A trailing odd byte is written as a final `\x` escape.
+pub(crate) fn encode_bytes(
+    encoder: &super::Encoder,
+    bytes: &[u8],
+    from_bytes: fn([u8; 2]) -> u16,
+) -> String {
+    let chunks = bytes.chunks_exact(2);
+    let odd = chunks.remainder();
+    let units: Vec<u16> = chunks.map(|c| from_bytes([c[0], c[1]])).collect();
+    let mut out = encode(encoder, &units);
+    if !odd.is_empty() {
+        helpers::escape_x(&mut out, encoder.escape_char, odd)
+            .expect("writing to a String cannot fail");
+    }
+    out
+}
+
 /// Encode u16 (i.e. almost UTF-16) into STFU-8.
 pub(crate) fn encode(encoder: &super::Encoder, v: &[u16]) -> String {
     let mut out = String::with_capacity(v.len() * 2);
+    encode_fmt(encoder, v, &mut out).expect("writing to a String cannot fail");
+    out
+}
+
+/// Encode u16 (i.e. almost UTF-16) into STFU-8, writing to any `fmt::Write`.
+pub(crate) fn encode_fmt<W: fmt::Write>(
+    encoder: &super::Encoder,
+    v: &[u16],
+    out: &mut W,
+) -> fmt::Result {
+    let (start, end) = helpers::edge_spaces(encoder, v);
+    for _ in 0..start {
+        helpers::escape_hex(out, encoder, b" ")?;
+    }
+    encode_units(encoder, &v[start..end], out)?;
+    for _ in end..v.len() {
+        helpers::escape_hex(out, encoder, b" ")?;
+    }
+    Ok(())
+}
 
+fn encode_units<W: fmt::Write>(encoder: &super::Encoder, v: &[u16], out: &mut W) -> fmt::Result {
     let mut iter = v.iter();
     let mut c16 = match iter.next() {
         Some(c) => *c,
-        None => return out,
+        None => return Ok(()),
     };
 
     loop {
         match c16 {
+            // shorthand escapes and whitespace, the same order as `encode_u8`
+            0x00..=0x7F
+                if helpers::short_escape(encoder, c16 as u8).is_some()
+                    || helpers::is_pretty_whitespace(c16 as u8) =>
+            {
+                helpers::escape_u8(out, encoder, c16 as u8)?
+            }
             // non-printable ascii
-            0x00..=0x1F | helpers::BSLASH_U16 => helpers::escape_u8(&mut out, encoder, c16 as u8),
+            0x00..=0x1F if helpers::is_literal_control(encoder, c16 as u8) => {
+                out.write_char(c16 as u8 as char)?
+            }
+            0x00..=0x1F => helpers::escape_u8(out, encoder, c16 as u8)?,
             // leading surrogates
             LEAD_MIN..=LEAD_MAX => {
                 let trail = match iter.next() {
                     Some(t) => *t,
                     None => {
                         // lead at end of u16 (no trail)
-                        helpers::escape_u16(&mut out, c16);
+                        helpers::escape_u16(out, encoder, c16)?;
                         break;
                     }
                 };
-                if !(TRAIL_MIN <= trail && trail <= TRAIL_MAX) {
+                if !(TRAIL_MIN..=TRAIL_MAX).contains(&trail) {
                     // lead without a trail, just escape it and handle the char on the next
                     // loop
-                    helpers::escape_u16(&mut out, c16);
+                    helpers::escape_u16(out, encoder, c16)?;
                     c16 = trail;
                     continue;
                 }
                 // has both a lead and a trail -- is valid!
                 let buf = [c16, trail];
-                out.push(char::from_u32(helpers::to_utf32(&buf)).unwrap());
+                let c = char::from_u32(helpers::to_utf32(&buf)).unwrap();
+                if helpers::escapes_supplementary(encoder) {
+                    helpers::escape_supplementary(out, encoder, c)?;
+                } else if encoder.ascii_only && encoder.short_u_escapes {
+                    // a short `\u` escape can't hold the char
+                    write!(out, "{}U{:0>8X}", encoder.escape_char, c as u32)?;
+                } else if encoder.ascii_only {
+                    write!(out, "{}u{:0>6X}", encoder.escape_char, c as u32)?;
+                } else {
+                    out.write_char(c)?;
+                }
             }
             // unpaired trailing surrogates
             TRAIL_MIN..=TRAIL_MAX => {
                 // trail without a lead
-                helpers::escape_u16(&mut out, c16);
+                helpers::escape_u16(out, encoder, c16)?;
+            }
+            0x2028 | 0x2029 if encoder.escape_line_separators => {
+                helpers::escape_u16(out, encoder, c16)?;
+            }
+            0x80..=0xFFFF if encoder.ascii_only => {
+                helpers::escape_u16(out, encoder, c16)?;
             }
             _ => {
-                out.push(char::from_u32(helpers::to_utf32(&[c16])).unwrap());
+                out.write_char(char::from_u32(helpers::to_utf32(&[c16])).unwrap())?;
             }
         }
         c16 = match iter.next() {
@@ -72,7 +138,7 @@ pub(crate) fn encode(encoder: &super::Encoder, v: &[u16]) -> String {
             None => break,
         };
     }
-    out
+    Ok(())
 }
 
 #[test]
@@ -82,7 +148,7 @@ fn sanity_encode() {
         println!("utf16: {:?}", utf16);
         let out = encode(&super::Encoder::new(), &utf16);
         // validation, we may use from_utf8_unchecked in the future
-        let _ = ::std::str::from_utf8(&out.as_bytes()).unwrap();
+        let _ = ::std::str::from_utf8(out.as_bytes()).unwrap();
         out
     }
     fn assert_enc(s: &str) {
@@ -111,3 +177,124 @@ fn sanity_encode() {
         /*   */ r"Ā ā Ă \nă Ą ą Ć\n ć Ĉ ĉ\n",
     );
 }
+
+#[test]
+fn sanity_encode_ascii_only() {
+    let encoder = super::Encoder::new().with_ascii_only(true);
+    let utf16: Vec<u16> = "café\t\u{1F600}\\".encode_utf16().collect();
+    let result = encode(&encoder, &utf16);
+    assert_eq!(result, r"caf\u0000E9\t\u01F600\\");
+    assert_eq!(crate::decode_u16(&result).unwrap(), utf16);
+    assert_eq!(
+        crate::decode_u8(&result).unwrap(),
+        "café\t\u{1F600}\\".as_bytes()
+    );
+
+    // ill-formed UTF-16
+    let utf16 = [0x61, 0xDE00, 0xD83D, 0x62, 0xD83D];
+    let result = encode(&encoder, &utf16);
+    assert_eq!(result, r"a\u00DE00\u00D83Db\u00D83D");
+    assert_eq!(crate::decode_u16(&result).unwrap(), utf16);
+
+    let encoder = encoder.with_escape_supplementary(true);
+    let utf16: Vec<u16> = "¡\u{1F600}".encode_utf16().collect();
+    assert_eq!(encode(&encoder, &utf16), r"\u0000A1\u{1F600}");
+}
+
+#[test]
+fn sanity_encode_pretty() {
+    let pretty = super::Encoder::pretty();
+    let utf16: Vec<u16> = "\tfoo\nbar\r\n\x07\\".encode_utf16().collect();
+    assert_eq!(encode(&pretty, &utf16), "\tfoo\nbar\r\n\\x07\\\\");
+    assert_eq!(
+        encode(&super::Encoder::new(), &utf16),
+        r"\tfoo\nbar\r\n\x07\\"
+    );
+}
+
+#[test]
+fn sanity_short_u_escapes() {
+    let encoder = super::Encoder::new().with_short_u_escapes(true);
+    let mut decoder = crate::Decoder::new();
+    decoder.short_u_escapes = true;
+
+    let v: Vec<u16> = vec![0xD800, 0x31, 0x1B, 0xDC00, 0xD83D, 0xDE00];
+    let encoded = encode(&encoder, &v);
+    assert_eq!(encoded, "\\uD8001\\x1B\\uDC00\u{1F600}");
+    assert_eq!(decoder.decode_u16(&encoded).unwrap(), v);
+    // the default width can't be decoded as short escapes
+    assert_ne!(
+        decoder.decode_u16(&encode(&crate::Encoder::new(), &v)),
+        Ok(v.clone())
+    );
+
+    let encoder = encoder.with_ascii_only(true);
+    let v: Vec<u16> = "é\u{1F600}".encode_utf16().collect();
+    let encoded = encode(&encoder, &v);
+    assert_eq!(encoded, r"\u00E9\U0001F600");
+    assert_eq!(decoder.decode_u16(&encoded).unwrap(), v);
+
+    assert_eq!(
+        decoder.decode_u8(r"\u00411\u{1F600}").unwrap(),
+        "A1\u{1F600}".as_bytes()
+    );
+    let err = decoder.decode_u8(r"\u004").unwrap_err();
+    assert_eq!(err.kind, crate::DecodeErrorKind::HexNumberToShort);
+}
+
+#[test]
+fn sanity_encode_bytes() {
+    let encoder = super::Encoder::new();
+    let text = "foo\tbar ¡ \u{1F600}";
+    let le: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+    let be: Vec<u8> = text.encode_utf16().flat_map(|c| c.to_be_bytes()).collect();
+    assert_eq!(
+        encode_bytes(&encoder, &le, u16::from_le_bytes),
+        "foo\\tbar ¡ \u{1F600}"
+    );
+    assert_eq!(
+        encode_bytes(&encoder, &be, u16::from_be_bytes),
+        "foo\\tbar ¡ \u{1F600}"
+    );
+    assert_eq!(encode_bytes(&encoder, &[], u16::from_le_bytes), "");
+
+    // a trailing odd byte is kept
+    let encoded = encode_bytes(&encoder, b"a\x00\x00\xDC\x0A", u16::from_le_bytes);
+    assert_eq!(encoded, r"a\u00DC00\x0A");
+    assert_eq!(crate::decode_u16(&encoded).unwrap(), [0x61, 0xDC00, 0x0A]);
+}
+
+#[test]
+fn sanity_encode_matches_u8() {
+    // encoders whose settings interact on the ASCII controls
+    let mut encoders = vec![super::Encoder::new(), super::Encoder::pretty()];
+    encoders.push(
+        super::Encoder::pretty()
+            .with_encode_tab(true)
+            .with_literal_controls(b"\t\n\x00\x1B"),
+    );
+    encoders.push(
+        super::Encoder::new()
+            .with_encode_null(true)
+            .with_hex_only_controls(true)
+            .with_literal_controls(b"\x00\r"),
+    );
+    encoders.push(super::Encoder::new().with_escape_char('%'));
+    encoders.push(
+        super::Encoder::pretty()
+            .with_encode_form_feed(false)
+            .with_encode_vtab(false),
+    );
+
+    // DEL is only escaped by `encode_u8`, see `escape_del`
+    let ascii: Vec<u8> = (0..0x7F).collect();
+    let utf16: Vec<u16> = ascii.iter().map(|b| u16::from(*b)).collect();
+    for encoder in &encoders {
+        assert_eq!(
+            encode(encoder, &utf16),
+            crate::encode_u8::encode(encoder, &ascii),
+            "{:?}",
+            encoder
+        );
+    }
+}
@@ -9,131 +9,720 @@
 //! This code is practically copy/pasted from the rust std libraries'
 //! `run_utf8_validation` function, used by `str::from_utf8`.
 
+use std::collections::TryReserveError;
+use std::convert::TryInto;
+use std::fmt;
+use std::mem;
 use std::str;
 
-use helpers;
+use crate::helpers;
+use crate::{EscapeMask, EscapeStyle};
 
 /*
 Section: UTF-8 validation
 */
 
-/// Pretty much an exact copy of `run_utf8_validation` from the rust stdlib.
 pub(crate) fn encode(encoder: &super::Encoder, v: &[u8]) -> String {
+    encode_with_capacity(encoder, v, v.len() + v.len() / 8)
+}
+
+pub(crate) fn encode_with_capacity(encoder: &super::Encoder, v: &[u8], capacity: usize) -> String {
+    let mut out = String::with_capacity(capacity);
+    encode_fmt(encoder, v, &mut out).expect("writing to a String cannot fail");
+    out
+}
+
+pub(crate) fn encode_masked(encoder: &super::Encoder, v: &[u8], mask: &EscapeMask) -> String {
+    let mut out = String::with_capacity(v.len() + v.len() / 8);
+    encode_masked_fmt(encoder, v, mask, &mut out).expect("writing to a String cannot fail");
+    out
+}
+
+/// The length of `encode(encoder, v)`, without building it.
+pub(crate) fn encoded_len(encoder: &super::Encoder, v: &[u8]) -> usize {
+    let mut counter = Counter(0);
+    encode_fmt(encoder, v, &mut counter).expect("counting cannot fail");
+    counter.0
+}
+
+/// Whether `encode(encoder, v)` differs from `v`, stopping at the first byte which is escaped.
+pub(crate) fn needs_encoding(encoder: &super::Encoder, v: &[u8]) -> bool {
+    if str::from_utf8(v).is_err() || helpers::edge_spaces(encoder, v) != (0, v.len()) {
+        return true;
+    }
     let mut index = 0;
-    let len = v.len();
-    let mut out = String::with_capacity(len + len / 8);
+    while index < v.len() {
+        index = ascii_run(encoder, v, index);
+        match v.get(index) {
+            None => break,
+            Some(&b) if b < 0x80 && !helpers::is_literal_ascii(encoder, b) => return true,
+            Some(&b) if b >= 0x80 && encoder.ascii_only => return true,
+            Some(&b) if b >= 0xF0 && helpers::escapes_supplementary(encoder) => return true,
+            Some(_) if helpers::is_line_separator(encoder, &v[index..]) => return true,
+            Some(_) => index += 1,
+        }
+    }
+    false
+}
+
+/// Like `encode(encoder, v)`, but returning an error instead of aborting if the output can't be
+/// allocated.
+pub(crate) fn try_encode(encoder: &super::Encoder, v: &[u8]) -> Result<String, TryReserveError> {
+    let mut out = TryString {
+        out: String::new(),
+        err: None,
+    };
+    out.out.try_reserve(v.len() + v.len() / 8)?;
+    match encode_fmt(encoder, v, &mut out) {
+        Ok(()) => Ok(out.out),
+        Err(_) => Err(out.err.expect("only reserving can fail")),
+    }
+}
 
-    while index < len {
-        let old_offset = index;
+/// A `String` which reserves its capacity fallibly, keeping the error.
+struct TryString {
+    out: String,
+    err: Option<TryReserveError>,
+}
 
-        /// write a single byte that may be ascii.
-        /// Escape it correctly no matter what.
-        macro_rules! maybe_ascii {
-            ($i: expr) => {{
-                let b = v[$i];
-                match b {
-                    helpers::BSLASH => helpers::escape_u8(&mut out, encoder, b),
-                    0x20..=0x7e => out.push(b as char), // visible ASCII
-                    0x00..=0x1F | 0x7f..=0xFF => helpers::escape_u8(&mut out, encoder, b),
-                }
-            }};
+impl fmt::Write for TryString {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if let Err(err) = self.out.try_reserve(s.len()) {
+            self.err = Some(err);
+            return Err(fmt::Error);
         }
+        self.out.push_str(s);
+        Ok(())
+    }
+}
 
-        /// Escape everything from `old_offset` to current index.
-        /// It is invalid STFU-8 (which might be invalid utf8,
-        /// or could just be the `\` character...)
-        macro_rules! escape_them {
-            () => {{
-                for i in old_offset..(index + 1) {
-                    maybe_ascii!(i);
-                }
-                index += 1;
-                continue;
-            }};
+/// Counts the bytes written to it.
+struct Counter(usize);
+
+impl fmt::Write for Counter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// Encode `v`, escaping the bytes marked in `mask` whether or not they need it.
+fn encode_masked_fmt<W: fmt::Write>(
+    encoder: &super::Encoder,
+    v: &[u8],
+    mask: &EscapeMask,
+    dst: &mut W,
+) -> fmt::Result {
+    let mut out = Output {
+        encoder,
+        dst,
+        group: Vec::new(),
+    };
+    let (start, end) = helpers::edge_spaces(encoder, v);
+    let is_escaped = |i: usize| mask.is_escaped(i) || i < start || i >= end;
+    let mut index = 0;
+    while index < v.len() {
+        if is_escaped(index) {
+            out.push_escaped(v[index])?;
+            index += 1;
+            continue;
+        }
+        // encode the unescaped run on its own, so that no char spans into an escaped byte
+        let end = (index..v.len()).find(|&i| is_escaped(i)).unwrap_or(v.len());
+        while index < end {
+            index = encode_next(&mut out, &v[..end], index)?;
         }
+    }
+    out.finish()
+}
 
-        /// write everything from `old_offset` to current-index -- it
-        /// is all valid utf8 and stfu8.
-        macro_rules! write_them {
-            () => {{
-                out.push_str(&str::from_utf8(&v[old_offset..(index + 1)]).unwrap());
-            }};
+pub(crate) fn encode_str(encoder: &super::Encoder, s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + s.len() / 8);
+    encode_str_fmt(encoder, s, &mut out).expect("writing to a String cannot fail");
+    out
+}
+
+/// Encode text which is known to be valid UTF-8.
+///
+/// The output is the same as `encode_fmt`, but only the ASCII bytes (and supplementary-plane
+/// chars when they are escaped) need to be looked at: everything else is copied as-is.
+fn encode_str_fmt<W: fmt::Write>(encoder: &super::Encoder, s: &str, dst: &mut W) -> fmt::Result {
+    if encoder.ascii_only {
+        // every non-ASCII byte is escaped, so there is nothing to copy as-is
+        return encode_fmt(encoder, s.as_bytes(), dst);
+    }
+    if encoder.escape_edge_spaces {
+        // the spaces are escaped depending on their position, which `encode_fmt` handles
+        return encode_fmt(encoder, s.as_bytes(), dst);
+    }
+    let mut out = Output {
+        encoder,
+        dst,
+        group: Vec::new(),
+    };
+    let escape = encoder.escape_char as u8;
+    let bytes = s.as_bytes();
+    // start of the bytes which have not been written yet
+    let mut start = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+        let b = bytes[index];
+        if b < 0x20 || b == 0x7F || b == escape {
+            out.push_str(&s[start..index])?;
+            out.push_byte(b)?;
+            index += 1;
+            start = index;
+        } else if b >= 0xF0 && helpers::escapes_supplementary(encoder) {
+            out.push_str(&s[start..index])?;
+            out.push_supplementary(&s[index..(index + 4)])?;
+            index += 4;
+            start = index;
+        } else if helpers::is_line_separator(encoder, &bytes[index..]) {
+            out.push_str(&s[start..index])?;
+            out.push_line_separator(&s[index..(index + 3)])?;
+            index += 3;
+            start = index;
+        } else {
+            index += 1;
         }
+    }
+    out.push_str(&s[start..])?;
+    out.finish()
+}
+
+pub(crate) fn encode_chars<I: Iterator<Item = char>>(encoder: &super::Encoder, chars: I) -> String {
+    let mut out = String::with_capacity(chars.size_hint().0);
+    if encoder.escape_edge_spaces {
+        // the trailing spaces are only known at the end of the chars
+        let s: String = chars.collect();
+        encode_fmt(encoder, s.as_bytes(), &mut out).expect("writing to a String cannot fail");
+        return out;
+    }
+    encode_chars_fmt(encoder, chars, &mut out).expect("writing to a String cannot fail");
+    out
+}
 
-        macro_rules! next {
-            () => {{
-                index += 1;
-                if index >= len {
-                    index -= 1;
-                    escape_them!();
+/// Encode chars one at a time. A `char` is always valid, so (like `encode_str_fmt`) only
+/// the ASCII and escaped chars need to be looked at.
+fn encode_chars_fmt<W: fmt::Write, I: Iterator<Item = char>>(
+    encoder: &super::Encoder,
+    chars: I,
+    dst: &mut W,
+) -> fmt::Result {
+    let mut out = Output {
+        encoder,
+        dst,
+        group: Vec::new(),
+    };
+    let mut buf = [0; 4];
+    for c in chars {
+        let s = c.encode_utf8(&mut buf);
+        if c.is_ascii() {
+            out.push_byte(c as u8)?;
+        } else if encoder.ascii_only {
+            for &b in s.as_bytes() {
+                out.push_hex(b)?;
+            }
+        } else if s.len() == 4 && helpers::escapes_supplementary(encoder) {
+            out.push_supplementary(s)?;
+        } else if helpers::is_line_separator(encoder, s.as_bytes()) {
+            out.push_line_separator(s)?;
+        } else {
+            out.push_str(s)?;
+        }
+    }
+    out.finish()
+}
+
+/// Pretty much an exact copy of `run_utf8_validation` from the rust stdlib.
+pub(crate) fn encode_fmt<W: fmt::Write>(
+    encoder: &super::Encoder,
+    v: &[u8],
+    dst: &mut W,
+) -> fmt::Result {
+    let mut out = Output {
+        encoder,
+        dst,
+        group: Vec::new(),
+    };
+    let (start, end) = helpers::edge_spaces(encoder, v);
+    for _ in 0..start {
+        out.push_hex(b' ')?;
+    }
+    let mut index = start;
+    let inner = &v[..end];
+    while index < end {
+        let run_end = ascii_run(encoder, inner, index);
+        // a single char isn't worth validating separately
+        if run_end > index + 1 {
+            out.push_str(str::from_utf8(&inner[index..run_end]).unwrap())?;
+            index = run_end;
+        } else {
+            index = encode_next(&mut out, inner, index)?;
+        }
+    }
+    for _ in end..v.len() {
+        out.push_hex(b' ')?;
+    }
+    out.finish()
+}
+
+/// The end of the run of visible ASCII starting at `index`, which is written as-is.
+fn ascii_run(encoder: &super::Encoder, v: &[u8], index: usize) -> usize {
+    let escape = encoder.escape_char as u8;
+    let is_visible = |b: &u8| (0x20..=0x7E).contains(b) && *b != escape;
+    // most runs in text are short, so only skip whole words (like `run_utf8_validation`) once
+    // the run is longer than one
+    let mut end = index;
+    let prefix = v[index..].iter().take(WORD_BYTES);
+    end += prefix.take_while(|b| is_visible(b)).count();
+    if end < index + WORD_BYTES {
+        return end;
+    }
+    while let Some(word) = v.get(end..(end + WORD_BYTES)) {
+        let word = usize::from_ne_bytes(word.try_into().unwrap());
+        if !is_visible_ascii(word, escape) {
+            break;
+        }
+        end += WORD_BYTES;
+    }
+    end + v[end..].iter().take_while(|b| is_visible(b)).count()
+}
+
+const WORD_BYTES: usize = mem::size_of::<usize>();
+/// The byte `0x01` repeated in a word.
+const LO_USIZE: usize = usize::MAX / 0xFF;
+/// The byte `0x80` repeated in a word.
+const NONASCII_MASK: usize = LO_USIZE * 0x80;
+
+/// Whether every byte of the word is visible ASCII (`0x20..=0x7E`) and not the escape character.
+fn is_visible_ascii(word: usize, escape: u8) -> bool {
+    // has a byte less than `n` (for `n <= 0x80`)
+    let has_less = |n: usize| word.wrapping_sub(LO_USIZE * n) & !word & NONASCII_MASK != 0;
+    // has a byte equal to `b`
+    let has_byte = |b: u8| {
+        let x = word ^ (LO_USIZE * b as usize);
+        x.wrapping_sub(LO_USIZE) & !x & NONASCII_MASK != 0
+    };
+    word & NONASCII_MASK == 0 && !has_less(0x20) && !has_byte(0x7F) && !has_byte(escape)
+}
+
+/// Encode the char (or invalid sequence of bytes) of `v` at `index`, returning the index of the
+/// next one.
+fn encode_next<W: fmt::Write>(
+    out: &mut Output<W>,
+    v: &[u8],
+    mut index: usize,
+) -> Result<usize, fmt::Error> {
+    let len = v.len();
+    let old_offset = index;
+
+    /// write a single byte that may be ascii.
+    /// Escape it correctly no matter what.
+    macro_rules! maybe_ascii {
+        ($i: expr) => {{
+            out.push_byte(v[$i])?;
+        }};
+    }
+
+    /// Escape everything from `old_offset` to current index.
+    /// It is invalid STFU-8 (which might be invalid utf8,
+    /// or could just be the `\` character...)
+    macro_rules! escape_them {
+        () => {{
+            for i in old_offset..(index + 1) {
+                maybe_ascii!(i);
+            }
+            index += 1;
+            return Ok(index);
+        }};
+    }
+
+    /// write everything from `old_offset` to current-index -- it
+    /// is all valid utf8 and stfu8.
+    ///
+    /// The output is built directly as a `String`, so there is no final validation of
+    /// it. Only this single char is re-validated, which is cheap and keeps the crate free
+    /// of `unsafe` (`from_utf8_unchecked`).
+    macro_rules! write_them {
+        () => {{
+            out.push_str(str::from_utf8(&v[old_offset..(index + 1)]).unwrap())?;
+        }};
+    }
+
+    macro_rules! next {
+        () => {{
+            index += 1;
+            if index >= len {
+                index -= 1;
+                escape_them!();
+            }
+            v[index]
+        }};
+    }
+
+    let first = v[index];
+    if first >= 128 && out.encoder.ascii_only {
+        out.push_hex(first)?;
+        index += 1;
+    } else if first >= 128 {
+        let w = UTF8_CHAR_WIDTH[first as usize];
+        // 2-byte encoding is for codepoints  \u{0080} to  \u{07ff}
+        //        first  C2 80        last DF BF
+        // 3-byte encoding is for codepoints  \u{0800} to  \u{ffff}
+        //        first  E0 A0 80     last EF BF BF
+        //   excluding surrogates codepoints  \u{d800} to  \u{dfff}
+        //               ED A0 80 to       ED BF BF
+        // 4-byte encoding is for codepoints \u{1000}0 to \u{10ff}ff
+        //        first  F0 90 80 80  last F4 8F BF BF
+        //
+        // Use the UTF-8 syntax from the RFC
+        //
+        // https://tools.ietf.org/html/rfc3629
+        // UTF8-1      = %x00-7F
+        // UTF8-2      = %xC2-DF UTF8-tail
+        // UTF8-3      = %xE0 %xA0-BF UTF8-tail / %xE1-EC 2( UTF8-tail ) /
+        //               %xED %x80-9F UTF8-tail / %xEE-EF 2( UTF8-tail )
+        // UTF8-4      = %xF0 %x90-BF 2( UTF8-tail ) / %xF1-F3 3( UTF8-tail ) /
+        //               %xF4 %x80-8F 2( UTF8-tail )
+        match w {
+            2 => {
+                if next!() & !CONT_MASK != TAG_CONT_U8 {
+                    escape_them!(); //orig: err!(Some(1))
+                }
+            }
+            3 => {
+                match (first, next!()) {
+                    (0xE0, 0xA0..=0xBF)
+                    | (0xE1..=0xEC, 0x80..=0xBF)
+                    | (0xED, 0x80..=0x9F)
+                    | (0xEE..=0xEF, 0x80..=0xBF) => {}
+                    _ => escape_them!(), // orig: err!(Some(1))
+                }
+                if next!() & !CONT_MASK != TAG_CONT_U8 {
+                    escape_them!(); //orig: err!(Some(2))
                 }
-                v[index]
-            }};
-        }
-
-        let first = v[index];
-        if first >= 128 {
-            let w = UTF8_CHAR_WIDTH[first as usize];
-            // 2-byte encoding is for codepoints  \u{0080} to  \u{07ff}
-            //        first  C2 80        last DF BF
-            // 3-byte encoding is for codepoints  \u{0800} to  \u{ffff}
-            //        first  E0 A0 80     last EF BF BF
-            //   excluding surrogates codepoints  \u{d800} to  \u{dfff}
-            //               ED A0 80 to       ED BF BF
-            // 4-byte encoding is for codepoints \u{1000}0 to \u{10ff}ff
-            //        first  F0 90 80 80  last F4 8F BF BF
-            //
-            // Use the UTF-8 syntax from the RFC
-            //
-            // https://tools.ietf.org/html/rfc3629
-            // UTF8-1      = %x00-7F
-            // UTF8-2      = %xC2-DF UTF8-tail
-            // UTF8-3      = %xE0 %xA0-BF UTF8-tail / %xE1-EC 2( UTF8-tail ) /
-            //               %xED %x80-9F UTF8-tail / %xEE-EF 2( UTF8-tail )
-            // UTF8-4      = %xF0 %x90-BF 2( UTF8-tail ) / %xF1-F3 3( UTF8-tail ) /
-            //               %xF4 %x80-8F 2( UTF8-tail )
-            match w {
-                2 => {
-                    if next!() & !CONT_MASK != TAG_CONT_U8 {
-                        escape_them!(); //orig: err!(Some(1))
-                    }
+            }
+            4 => {
+                match (first, next!()) {
+                    (0xF0, 0x90..=0xBF) | (0xF1..=0xF3, 0x80..=0xBF) | (0xF4, 0x80..=0x8F) => {}
+                    _ => escape_them!(), //orig: err!(Some(1))
                 }
-                3 => {
-                    match (first, next!()) {
-                        (0xE0, 0xA0..=0xBF)
-                        | (0xE1..=0xEC, 0x80..=0xBF)
-                        | (0xED, 0x80..=0x9F)
-                        | (0xEE..=0xEF, 0x80..=0xBF) => {}
-                        _ => escape_them!(), // orig: err!(Some(1))
-                    }
-                    if next!() & !CONT_MASK != TAG_CONT_U8 {
-                        escape_them!(); //orig: err!(Some(2))
-                    }
+                if next!() & !CONT_MASK != TAG_CONT_U8 {
+                    escape_them!(); //orig: err!(Some(2))
                 }
-                4 => {
-                    match (first, next!()) {
-                        (0xF0, 0x90..=0xBF) | (0xF1..=0xF3, 0x80..=0xBF) | (0xF4, 0x80..=0x8F) => {}
-                        _ => escape_them!(), //orig: err!(Some(1))
-                    }
-                    if next!() & !CONT_MASK != TAG_CONT_U8 {
-                        escape_them!(); //orig: err!(Some(2))
-                    }
-                    if next!() & !CONT_MASK != TAG_CONT_U8 {
-                        escape_them!(); //orig: err!(Some(3))
-                    }
+                if next!() & !CONT_MASK != TAG_CONT_U8 {
+                    escape_them!(); //orig: err!(Some(3))
                 }
-                _ => escape_them!(), //orig: err!(Some(1))
             }
-            // they were not invalid, so they are valid
-            write_them!();
-            index += 1;
+            _ => escape_them!(), //orig: err!(Some(1))
+        }
+        // they were not invalid, so they are valid
+        if w == 4 && helpers::escapes_supplementary(out.encoder) {
+            let c = str::from_utf8(&v[old_offset..(index + 1)]).unwrap();
+            out.push_supplementary(c)?;
+        } else if w == 3 && helpers::is_line_separator(out.encoder, &v[old_offset..]) {
+            let c = str::from_utf8(&v[old_offset..(index + 1)]).unwrap();
+            out.push_line_separator(c)?;
         } else {
-            // Ascii case
-            maybe_ascii!(index);
-            index += 1;
+            write_them!();
         }
+        index += 1;
+    } else {
+        // Ascii case
+        maybe_ascii!(index);
+        index += 1;
+    }
+    Ok(index)
+}
+
+/// An iterator over the chars of bytes encoded as STFU-8, created by
+/// [`encode_u8_iter`](fn.encode_u8_iter.html).
+///
+/// The input is encoded lazily, one char (or invalid byte) at a time.
+#[derive(Debug, Clone)]
+pub struct EncodeU8Iter<'a> {
+    encoder: super::Encoder,
+    v: &'a [u8],
+    index: usize,
+    /// `Output::group` between calls to `next`.
+    group: Vec<u8>,
+    /// The encoding of the previous char, which is being yielded.
+    buf: String,
+    pos: usize,
+}
+
+impl<'a> EncodeU8Iter<'a> {
+    pub(crate) fn new(encoder: super::Encoder, v: &'a [u8]) -> EncodeU8Iter<'a> {
+        EncodeU8Iter {
+            encoder,
+            v,
+            index: 0,
+            group: Vec::new(),
+            buf: String::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for EncodeU8Iter<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        while self.pos >= self.buf.len() {
+            if self.index >= self.v.len() && self.group.is_empty() {
+                return None;
+            }
+            self.buf.clear();
+            self.pos = 0;
+            let mut out = Output {
+                encoder: &self.encoder,
+                dst: &mut self.buf,
+                group: mem::take(&mut self.group),
+            };
+            if self.index < self.v.len() {
+                self.index = encode_next(&mut out, self.v, self.index)
+                    .expect("writing to a String cannot fail");
+            } else {
+                out.flush().expect("writing to a String cannot fail");
+            }
+            self.group = out.group;
+        }
+        let c = self.buf[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+}
+
+/// An encoder of bytes which arrive in chunks, created by
+/// [`Encoder::encode_u8_stream`](struct.Encoder.html#method.encode_u8_stream).
+///
+/// A UTF-8 char may be split across chunks, so the bytes at the end of a chunk which could start
+/// an incomplete char are held back until the next [`push`](#method.push), and only escaped by
+/// [`finish`](#method.finish) if they never complete. The output is the same as `encode_u8` of
+/// all of the chunks joined, no matter how the input is split.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let mut stream = stfu8::Encoder::new().encode_u8_stream();
+/// let mut out = stream.push(b"foo\n\xE2\x9D");
+/// assert_eq!(out, r"foo\n");
+/// out += &stream.push(b"\xA4\xFF");
+/// out += &stream.finish();
+/// assert_eq!(out, "foo\\n\u{2764}\\xFF");
+/// assert_eq!(out, stfu8::encode_u8(b"foo\n\xE2\x9D\xA4\xFF"));
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamEncoder {
+    encoder: super::Encoder,
+    /// The start of a char which may be completed by the next chunk.
+    pending: Vec<u8>,
+    /// `Output::group` between chunks.
+    group: Vec<u8>,
+    /// Whether anything but spaces was pushed, after which spaces are no longer leading.
+    started: bool,
+}
+
+impl StreamEncoder {
+    pub(crate) fn new(encoder: super::Encoder) -> StreamEncoder {
+        StreamEncoder {
+            encoder,
+            pending: Vec::new(),
+            group: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Encode the next chunk of bytes, returning the output which is complete so far.
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+        let v = mem::take(&mut self.pending);
+        let encoder = self.encoder;
+        let mut dst = String::with_capacity(v.len() + v.len() / 8);
+        let mut out = Output {
+            encoder: &encoder,
+            dst: &mut dst,
+            group: mem::take(&mut self.group),
+        };
+        let (index, end) = self.edge_spaces(&v);
+        for _ in 0..index {
+            out.push_hex(b' ').expect("writing to a String cannot fail");
+        }
+        self.started |= index < v.len();
+        // trailing spaces are held back, since they may not be at the end of the input
+        let mut index = index;
+        let inner = &v[..end];
+        while index < end {
+            let run_end = ascii_run(&encoder, inner, index);
+            if run_end > index + 1 {
+                out.push_str(str::from_utf8(&inner[index..run_end]).unwrap())
+                    .expect("writing to a String cannot fail");
+                index = run_end;
+            } else if is_incomplete(&encoder, &inner[index..]) {
+                break;
+            } else {
+                index =
+                    encode_next(&mut out, inner, index).expect("writing to a String cannot fail");
+            }
+        }
+        self.group = out.group;
+        self.pending = v[index..].to_vec();
+        dst
+    }
+
+    /// Encode the bytes which were held back, returning the rest of the output.
+    pub fn finish(self) -> String {
+        let (mut index, end) = self.edge_spaces(&self.pending);
+        let mut dst = String::new();
+        let mut out = Output {
+            encoder: &self.encoder,
+            dst: &mut dst,
+            group: self.group,
+        };
+        for _ in 0..index {
+            out.push_hex(b' ').expect("writing to a String cannot fail");
+        }
+        let inner = &self.pending[..end];
+        while index < end {
+            index = encode_next(&mut out, inner, index).expect("writing to a String cannot fail");
+        }
+        for _ in end..self.pending.len() {
+            out.push_hex(b' ').expect("writing to a String cannot fail");
+        }
+        out.finish().expect("writing to a String cannot fail");
+        dst
+    }
+
+    /// The range of `v` between its leading and trailing spaces if they are escaped, where the
+    /// spaces are only leading if nothing else was pushed before.
+    fn edge_spaces(&self, v: &[u8]) -> (usize, usize) {
+        if !self.encoder.escape_edge_spaces {
+            return (0, v.len());
+        }
+        let is_space = |b: &&u8| **b == b' ';
+        let start = match self.started {
+            true => 0,
+            false => v.iter().take_while(is_space).count(),
+        };
+        (
+            start,
+            v.len() - v[start..].iter().rev().take_while(is_space).count(),
+        )
+    }
+}
+
+/// Whether `v` starts with the lead byte of a char which is longer than `v`, so more input may
+/// complete it.
+fn is_incomplete(encoder: &super::Encoder, v: &[u8]) -> bool {
+    let width = UTF8_CHAR_WIDTH[v[0] as usize] as usize;
+    !encoder.ascii_only && width > v.len()
+}
+
+/// The encoded output.
+///
+/// Bytes which must be escaped are grouped so that each `\x` escape holds `x_digits / 2` bytes.
+/// Once a group is started it is completed with the following bytes (whatever they are), and
+/// only the final escape of the output may be short.
+struct Output<'a, W: fmt::Write + 'a> {
+    encoder: &'a super::Encoder,
+    dst: &'a mut W,
+    group: Vec<u8>,
+}
+
+impl<'a, W: fmt::Write> Output<'a, W> {
+    /// Write a single byte that may be ascii. Escape it correctly no matter what.
+    fn push_byte(&mut self, b: u8) -> fmt::Result {
+        if !self.group.is_empty() {
+            return self.push_hex(b);
+        }
+        match b {
+            _ if helpers::short_escape(self.encoder, b).is_some() => {
+                helpers::escape_u8(self.dst, self.encoder, b)
+            }
+            _ if helpers::is_literal_ascii(self.encoder, b) => self.dst.write_char(b as char),
+            // includes whitespace with `hex_only_controls`, which must be grouped like the rest
+            _ => self.push_hex(b),
+        }
+    }
+
+    /// Escape a byte even if it doesn't need to be, preferring its shorthand.
+    fn push_escaped(&mut self, b: u8) -> fmt::Result {
+        if !self.group.is_empty() || self.encoder.escape_style == EscapeStyle::Percent {
+            return self.push_hex(b);
+        }
+        let letter = match helpers::short_escape(self.encoder, b) {
+            Some(letter) => letter,
+            // a "pretty" encoder doesn't escape whitespace, but still has the shorthands
+            None if helpers::is_pretty_whitespace(b) && !self.encoder.hex_only_controls => {
+                match b {
+                    b'\t' => 't',
+                    b'\n' => 'n',
+                    _ => 'r',
+                }
+            }
+            None => return self.push_hex(b),
+        };
+        self.dst.write_char(self.encoder.escape_char)?;
+        self.dst.write_char(letter)
+    }
+
+    /// Write valid UTF-8 and STFU-8.
+    fn push_str(&mut self, s: &str) -> fmt::Result {
+        let mut start = 0;
+        while start < s.len() && (!self.group.is_empty() || !s.is_char_boundary(start)) {
+            self.push_hex(s.as_bytes()[start])?;
+            start += 1;
+        }
+        self.dst.write_str(&s[start..])
+    }
+
+    /// Write a single supplementary-plane char as a `\u{...}` or `\U` escape.
+    fn push_supplementary(&mut self, c: &str) -> fmt::Result {
+        if !self.group.is_empty() {
+            // the bytes complete the current group instead
+            return self.push_str(c);
+        }
+        let c = c.chars().next().expect("a single char");
+        helpers::escape_supplementary(self.dst, self.encoder, c)
+    }
+
+    /// Write a line or paragraph separator as a `\u` escape.
+    fn push_line_separator(&mut self, c: &str) -> fmt::Result {
+        if !self.group.is_empty() {
+            // the bytes complete the current group instead
+            return self.push_str(c);
+        }
+        let c = c.chars().next().expect("a single char");
+        helpers::escape_line_separator(self.dst, self.encoder, c)
+    }
+
+    fn push_hex(&mut self, b: u8) -> fmt::Result {
+        if self.group.is_empty() && self.encoder.x_digits <= 2 {
+            // no need to buffer single byte escapes
+            return helpers::escape_hex(self.dst, self.encoder, &[b]);
+        }
+        self.group.push(b);
+        if self.group.len() * 2 >= self.encoder.x_digits {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> fmt::Result {
+        if !self.group.is_empty() {
+            helpers::escape_hex(self.dst, self.encoder, &self.group)?;
+            self.group.clear();
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> fmt::Result {
+        self.flush()
     }
-    out
 }
 
 // https://tools.ietf.org/html/rfc3629
@@ -166,7 +755,7 @@ fn sanity_encode() {
     fn enc(s: &str) -> String {
         let out = encode(&super::Encoder::new(), s.as_bytes());
         // validation, we may use from_utf8_unchecked in the future
-        let _ = ::std::str::from_utf8(&out.as_bytes()).unwrap();
+        let _ = ::std::str::from_utf8(out.as_bytes()).unwrap();
         out
     }
     fn assert_enc(s: &str) {
@@ -208,9 +797,373 @@ fn sanity_encode_binary() {
     );
 }
 
+#[test]
+fn sanity_encode_x_digits() {
+    let mut encoder = super::Encoder::new();
+    encoder.x_digits = 4;
+    let enc = |v: &[u8]| encode(&encoder, v);
+    assert_eq!(enc(b"foo\x01\x02bar"), r"foo\x0102bar");
+    // a partial group is completed with the following bytes
+    assert_eq!(enc(b"foo\x01bar"), r"foo\x0162ar");
+    assert_eq!(enc(b"\xFF\\\n"), r"\xFF5C\n");
+    assert_eq!(enc("\x01¡ ¢".as_bytes()), r"\x01C2\xA120¢");
+    // only the final escape is short
+    assert_eq!(enc(b"foo\xFE"), r"foo\xFE");
+    assert_eq!(enc(b"foo\x01\x02\x03"), r"foo\x0102\x03");
+}
+
+#[test]
+fn sanity_encode_literal_controls() {
+    let bytes = b"\x1B[1m\x07\x7F\x00\x9B\t";
+    assert_eq!(
+        encode(&super::Encoder::new(), bytes),
+        r"\x1B[1m\x07\x7F\x00\x9B\t"
+    );
+
+    let mut encoder = super::Encoder::new();
+    encoder.escape_del = false;
+    encoder.literal_controls = b"\x1B\x07\x9B";
+    assert_eq!(encode(&encoder, bytes), "\x1B[1m\x07\x7F\\x00\\x9B\\t");
+}
+
+#[test]
+fn sanity_encode_form_feed_vtab() {
+    let bytes = b"foo\x0C\x0Bbar\n";
+    assert_eq!(
+        encode(&super::Encoder::pretty(), bytes),
+        "foo\\x0C\\x0Bbar\n"
+    );
+
+    let encoder = super::Encoder::pretty().with_encode_form_feed(false);
+    assert_eq!(encode(&encoder, bytes), "foo\x0C\\x0Bbar\n");
+    let encoder = encoder.with_encode_vtab(false);
+    assert_eq!(encode(&encoder, bytes), "foo\x0C\x0Bbar\n");
+    assert_eq!(crate::decode_u8(&encode(&encoder, bytes)).unwrap(), bytes);
+}
+
+#[test]
+fn sanity_encode_escape_char() {
+    let mut encoder = super::Encoder::new();
+    encoder.escape_char = '%';
+    assert_eq!(encode(&encoder, b"C:\\foo%\n\xFF"), r"C:\foo%%%n%xFF");
+}
+
+#[test]
+fn sanity_encode_percent() {
+    let encoder = super::Encoder::new().with_escape_style(EscapeStyle::Percent);
+    assert_eq!(encoder.escape_char, '%');
+    assert_eq!(
+        encode(&encoder, b"C:\\foo 100%\t\n\x00\xFF"),
+        r"C:\foo 100%25%09%0A%00%FF"
+    );
+    assert_eq!(
+        encode(&encoder.with_ascii_only(true), "é".as_bytes()),
+        "%C3%A9"
+    );
+    let encoder = encoder.with_escape_supplementary(true);
+    assert_eq!(encoder.encode_str("a\u{1F600}"), "a%F0%9F%98%80");
+
+    let encoder = super::Encoder::pretty().with_escape_style(EscapeStyle::Percent);
+    assert_eq!(encode(&encoder, b"%\t\n\x7F"), "%25\t\n%7F");
+    let mask: EscapeMask = [true, false, true].iter().cloned().collect();
+    assert_eq!(encoder.encode_u8_masked(b"a\nb", &mask), "%61\n%62");
+}
+
+#[test]
+#[should_panic(expected = "EscapeStyle::Percent requires")]
+fn sanity_encode_percent_x_digits() {
+    let encoder = super::Encoder::new()
+        .with_escape_style(EscapeStyle::Percent)
+        .with_x_digits(4);
+    encoder.encode_u8(b"foo");
+}
+
+#[test]
+fn sanity_stream_encoder() {
+    let inputs: [&[u8]; 4] = [
+        b"foo\\\xF0\x9F\x98\x80\xE2\x9D\xA4\xFF\xE2\x9Dbar\xF0\x9F\x98",
+        b"  foo  bar \xE2 ",
+        b"   ",
+        b"",
+    ];
+    let encoders = [
+        super::Encoder::new(),
+        super::Encoder::pretty().with_x_digits(4),
+        super::Encoder::new().with_escape_supplementary(true),
+        super::Encoder::new().with_ascii_only(true),
+        super::Encoder::new().with_escape_edge_spaces(true),
+        super::Encoder::new()
+            .with_escape_edge_spaces(true)
+            .with_x_digits(4),
+    ];
+    for (encoder, v) in encoders
+        .iter()
+        .flat_map(|e| inputs.iter().map(move |v| (e, v)))
+    {
+        let expected = encode(encoder, v);
+        for size in 1..=v.len().max(1) {
+            let mut stream = encoder.encode_u8_stream();
+            let mut out = String::new();
+            for chunk in v.chunks(size) {
+                out += &stream.push(chunk);
+            }
+            out += &stream.finish();
+            assert_eq!(out, expected, "{:?} {}", encoder, size);
+        }
+    }
+}
+
+#[test]
+fn sanity_escape_edge_spaces() {
+    let encoder = super::Encoder::new().with_escape_edge_spaces(true);
+    let v = b"  foo bar\n ";
+    assert_eq!(encode(&encoder, v), r"\x20\x20foo bar\n\x20");
+    assert_eq!(encoder.encode_str("  foo bar\n "), r"\x20\x20foo bar\n\x20");
+    assert_eq!(encoder.encoded_len_u8(v), 21);
+    assert_eq!(crate::decode_u8(&encode(&encoder, v)).unwrap(), v);
+    assert_eq!(encode(&encoder, b"  "), r"\x20\x20");
+    assert_eq!(encode(&encoder, b"foo bar"), "foo bar");
+    assert_eq!(encode(&encoder.with_x_digits(4), b" \xFF "), r"\x20FF\x20");
+
+    assert!(!needs_encoding(&encoder, b"foo bar"));
+    assert!(needs_encoding(&encoder, b" foo"));
+    assert!(needs_encoding(&encoder, b"foo "));
+
+    let mask: EscapeMask = [false, true].iter().cloned().collect();
+    assert_eq!(encoder.encode_u8_masked(b" a ", &mask), r"\x20\x61\x20");
+
+    let v16: Vec<u16> = " \u{1F600} ".encode_utf16().collect();
+    assert_eq!(encoder.encode_u16(&v16), "\\x20\u{1F600}\\x20");
+    assert_eq!(crate::decode_u16(&encoder.encode_u16(&v16)).unwrap(), v16);
+}
+
+#[test]
+fn sanity_escape_line_separators() {
+    let s = "a\u{2028}b\u{2029}\n\u{2027}";
+    assert_eq!(encode(&super::Encoder::pretty(), s.as_bytes()), s);
+    let encoder = super::Encoder::pretty().with_escape_line_separators(true);
+    let expected = "a\\u002028b\\u002029\n\u{2027}";
+    assert_eq!(encode(&encoder, s.as_bytes()), expected);
+    assert_eq!(encoder.encode_str(s), expected);
+    assert_eq!(encoder.encode_chars(s.chars()), expected);
+    assert!(needs_encoding(&encoder, "\u{2029}".as_bytes()));
+    assert!(!needs_encoding(&encoder, "\u{2027}".as_bytes()));
+    assert_eq!(
+        crate::decode_u8(&encode(&encoder, s.as_bytes())).unwrap(),
+        s.as_bytes()
+    );
+
+    let v16: Vec<u16> = s.encode_utf16().collect();
+    assert_eq!(encoder.encode_u16(&v16), expected);
+    assert_eq!(
+        encode(&encoder.with_short_u_escapes(true), "\u{2028}".as_bytes()),
+        r"\u2028"
+    );
+    let percent = encoder.with_escape_style(crate::EscapeStyle::Percent);
+    assert_eq!(encode(&percent, "\u{2028}".as_bytes()), "%E2%80%A8");
+}
+
+#[test]
+fn sanity_try_encode() {
+    let encoder = super::Encoder::new();
+    for v in &[
+        &b""[..],
+        b"foo\xFF\nbar",
+        &[0xFF; 300],
+        "\u{1F600}".as_bytes(),
+    ] {
+        assert_eq!(try_encode(&encoder, v).unwrap(), encode(&encoder, v));
+    }
+}
+
+#[test]
+fn sanity_encode_null() {
+    let mut encoder = super::Encoder::new();
+    assert_eq!(encode(&encoder, b"foo\x00\n"), r"foo\x00\n");
+    encoder.encode_null = true;
+    assert_eq!(encode(&encoder, b"foo\x00\n"), r"foo\0\n");
+    assert_eq!(encode(&encoder, b"\x00\x000"), r"\0\00");
+}
+
+#[test]
+fn sanity_needs_encoding() {
+    let encoder = super::Encoder::new();
+    assert!(!needs_encoding(&encoder, b""));
+    assert!(!needs_encoding(
+        &encoder,
+        "a long line of text, ¡ \u{1F600}".as_bytes()
+    ));
+    assert!(needs_encoding(&encoder, b"a long line of text\\"));
+    assert!(needs_encoding(&encoder, b"a long line of text\x7F"));
+    assert!(needs_encoding(&encoder, b"\xC2"));
+
+    let encoder = super::Encoder::pretty().with_literal_controls(b"\x1B");
+    assert!(!needs_encoding(&encoder, b"\x1B[31mred\t\r\n"));
+    assert!(needs_encoding(&encoder, b"\x1B[31mred\x00"));
+    let encoder = encoder.with_escape_char('%');
+    assert!(!needs_encoding(&encoder, b"C:\\foo"));
+    assert!(needs_encoding(&encoder, b"100%"));
+    let encoder = encoder.with_escape_supplementary(true);
+    assert!(!needs_encoding(&encoder, "¡ \u{FFFF}".as_bytes()));
+    assert!(needs_encoding(&encoder, "\u{1F600}".as_bytes()));
+}
+
+#[test]
+fn sanity_encode_ascii_only() {
+    let encoder = super::Encoder::new().with_ascii_only(true);
+    let text = "café\t\u{1F600}\\";
+    let result = encode(&encoder, text.as_bytes());
+    assert_eq!(result, r"caf\xC3\xA9\t\xF0\x9F\x98\x80\\");
+    assert!(result.is_ascii());
+    assert_eq!(crate::decode_u8(&result).unwrap(), text.as_bytes());
+    assert_eq!(encode_str(&encoder, text), result);
+    assert_eq!(encoded_len(&encoder, text.as_bytes()), result.len());
+    let iter: String = EncodeU8Iter::new(encoder, text.as_bytes()).collect();
+    assert_eq!(iter, result);
+    assert!(needs_encoding(&encoder, "é".as_bytes()));
+
+    // invalid UTF-8 and grouped escapes
+    let encoder = encoder.with_x_digits(4);
+    assert_eq!(encode(&encoder, b"a\xC3\xA9\xFFb"), r"a\xC3A9\xFF62");
+
+    // normal mode is unchanged
+    assert_eq!(
+        encode(&super::Encoder::new(), text.as_bytes()),
+        "café\\t\u{1F600}\\\\"
+    );
+}
+
+#[test]
+fn sanity_encode_hex_only_controls() {
+    let mut encoder = super::Encoder::new();
+    encoder.hex_only_controls = true;
+    let result = encode(&encoder, b"foo\tbar\r\n\\");
+    assert_eq!(result, r"foo\x09bar\x0D\x0A\\");
+    assert!(!result.contains(r"\n"));
+    assert_eq!(crate::decode_u8(&result).unwrap(), b"foo\tbar\r\n\\");
+
+    let mut encoder = super::Encoder::pretty();
+    encoder.hex_only_controls = true;
+    assert_eq!(encode(&encoder, b"foo\n\xFF"), "foo\n\\xFF");
+
+    // the whitespace is grouped like any other escaped byte
+    let encoder = super::Encoder::new()
+        .with_hex_only_controls(true)
+        .with_x_digits(4);
+    assert_eq!(encode(&encoder, b"\n\n\t"), r"\x0A0A\x09");
+}
+
+#[test]
+fn sanity_encode_supplementary() {
+    let mut encoder = super::Encoder::new();
+    encoder.escape_supplementary = true;
+    let text = "smile \u{1F600} \u{00A2}\n";
+    let result = encode(&encoder, text.as_bytes());
+    assert_eq!(result, "smile \\u{1F600} \u{00A2}\\n");
+    assert_eq!(crate::decode_u8(&result).unwrap(), text.as_bytes());
+
+    let utf16: Vec<u16> = text.encode_utf16().collect();
+    assert_eq!(crate::encode_u16::encode(&encoder, &utf16), result);
+
+    encoder.escape_supplementary_long = true;
+    let result = encode(&encoder, text.as_bytes());
+    assert_eq!(result, "smile \\U0001F600 \u{00A2}\\n");
+    assert_eq!(crate::decode_u8(&result).unwrap(), text.as_bytes());
+    assert_eq!(crate::encode_u16::encode(&encoder, &utf16), result);
+    assert_eq!(encode_str(&encoder, text), result);
+    assert_eq!(encoded_len(&encoder, text.as_bytes()), result.len());
+}
+
+#[test]
+fn sanity_encode_iter() {
+    let mut samples: Vec<Vec<u8>> = vec![
+        b"".to_vec(),
+        b"foo\xFF\nbar\\".to_vec(),
+        b"\xC2\xC2\xA2\xE0\xA0A\xF0\x9F\x98".to_vec(),
+        (0..=255).collect(),
+    ];
+    samples.push("¡ ¢ £ \u{1F600}".as_bytes().to_vec());
+
+    for x_digits in &[2, 4, 6] {
+        let mut encoder = super::Encoder::new();
+        encoder.x_digits = *x_digits;
+        for v in &samples {
+            let expected = encode(&encoder, v);
+            let result: String = EncodeU8Iter::new(encoder, v).collect();
+            assert_eq!(result, expected);
+        }
+    }
+    assert_eq!(
+        crate::encode_u8_iter(b"\tfoo\xFF").collect::<String>(),
+        crate::encode_u8(b"\tfoo\xFF")
+    );
+}
+
 #[test]
 fn sanity_encode_pretty() {
     let expected = "foo\nbar\n";
     let result = encode(&super::Encoder::pretty(), expected.as_bytes());
     assert_eq!(expected, result);
 }
+
+#[test]
+fn sanity_ascii_run() {
+    let encoder = super::Encoder::new();
+    let visible: Vec<u8> = (0x20..=0x7E).filter(|b| *b != b'\\').collect();
+    assert_eq!(ascii_run(&encoder, &visible, 0), visible.len());
+    assert_eq!(ascii_run(&encoder, &visible, 50), visible.len());
+    // every position of a word, and after it
+    for i in 0..(3 * WORD_BYTES) {
+        for &b in &[0x00, 0x1F, 0x7F, 0x80, 0xFF, b'\\'] {
+            let mut v = visible.clone();
+            v[i] = b;
+            assert_eq!(ascii_run(&encoder, &v, 0), i, "{} {:#x}", i, b);
+        }
+    }
+    let encoder = encoder.with_escape_char('%');
+    assert_eq!(ascii_run(&encoder, b"abcdefghijkl%nopqrstuvwxyz\\", 0), 12);
+}
+
+#[test]
+fn sanity_encode_with() {
+    let encoder = super::Encoder::pretty();
+    let v = "a\"¡\n\tb\\".as_bytes();
+    assert_eq!(encoder.encode_u8_with(v, |_| false), encode(&encoder, v));
+    assert_eq!(encoder.encode_u8_with(v, |b| b == b'"'), "a\\x22¡\n\tb\\\\");
+    assert_eq!(encoder.encode_u8_with(v, |b| b == b'\t'), "a\"¡\n\\tb\\\\");
+    // only the flagged byte of the char is escaped, the rest is invalid on its own
+    assert_eq!(
+        encoder.encode_u8_with(v, |b| b == 0xA1),
+        "a\"\\xC2\\xA1\n\tb\\\\"
+    );
+    assert_eq!(
+        encoder.encode_u8_with(v, |b| b.is_ascii_alphabetic()),
+        "\\x61\"¡\n\t\\x62\\\\"
+    );
+}
+
+#[test]
+fn sanity_encode_masked() {
+    let pretty = super::Encoder::pretty();
+    for s in &[
+        r"fooA\x41\t\n\r\\\xFF",
+        "foo\\xC2\u{A1}\\n\n\t\\t",
+        r"\x41\x42\x43\x44\x45",
+    ] {
+        let (v, mask) = crate::decode_u8_masked(s).unwrap();
+        assert_eq!(encode_masked(&pretty, &v, &mask), *s);
+    }
+
+    let (v, mask) = crate::decode_u8_masked(r"A\x41\x42B\x43").unwrap();
+    let encoder = super::Encoder::new().with_x_digits(4).with_escape_char('%');
+    assert_eq!(encode_masked(&encoder, &v, &mask), "A%x4142B%x43");
+    let encoder = super::Encoder::new().with_hex_only_controls(true);
+    let (v, mask) = crate::decode_u8_masked(r"\n\x0A").unwrap();
+    assert_eq!(encode_masked(&encoder, &v, &mask), r"\x0A\x0A");
+
+    // bytes which are not in the mask are encoded as usual
+    assert_eq!(
+        encode_masked(&pretty, b"\xFFfoo\n", &EscapeMask::default()),
+        "\\xFFfoo\n"
+    );
+}
@@ -6,13 +6,12 @@
  * copied, modified, or distributed except according to those terms.
  */
 
-use std::fmt::Write;
-use std::u16;
-use std::u32;
+use std::fmt;
 
-/// the only visible character we escape
-pub(crate) const BSLASH: u8 = b'\\';
-pub(crate) const BSLASH_U16: u16 = BSLASH as u16;
+use crate::EscapeStyle;
+
+/// the only visible character we escape (by default)
+pub(crate) const BSLASH: char = '\\';
 
 const SURROGATE_OFFSET: i64 = 0x1_0000 - (0xD800 << 10) - 0xDC00;
 
@@ -33,36 +32,224 @@ pub(crate) fn to_utf32(v: &[u16]) -> u32 {
     }
 }
 
-pub(crate) fn escape_u8(dst: &mut String, encoder: &super::Encoder, b: u8) {
+/// Parse ascii hex digits (case-insensitive) into a value.
+///
+/// Returns `None` if any byte is not a hex digit. There must be at most 8 digits.
+pub(crate) fn from_hex(digits: &[u8]) -> Option<u32> {
+    let mut val: u32 = 0;
+    for d in digits {
+        let n = match *d {
+            b'0'..=b'9' => d - b'0',
+            b'a'..=b'f' => d - b'a' + 10,
+            b'A'..=b'F' => d - b'A' + 10,
+            _ => return None,
+        };
+        val = (val << 4) | u32::from(n);
+    }
+    Some(val)
+}
+
+/// Get the letter of the shorthand escape for a byte (i.e. `n` for `\n`), if the encoder uses
+/// one for it.
+pub(crate) fn short_escape(encoder: &super::Encoder, b: u8) -> Option<char> {
     match b {
-        b'\\' => dst.push_str(r"\\"),
-        b'\t' => {
-            if encoder.encode_tab {
-                dst.push_str("\\t");
-            } else {
-                dst.push(b as char);
-            }
+        // the escape character escapes itself
+        _ if b == encoder.escape_char as u8 => Some(encoder.escape_char),
+        // escaped as `\x` instead
+        _ if is_pretty_whitespace(b) && encoder.hex_only_controls => None,
+        b'\t' if encoder.encode_tab => Some('t'),
+        b'\n' if encoder.encode_line_feed => Some('n'),
+        b'\r' if encoder.encode_cariage => Some('r'),
+        0x00 if encoder.encode_null => Some('0'),
+        _ => None,
+    }
+}
+
+/// How a byte is written when encoding `u8`, see [`classify_byte`](fn.classify_byte.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ByteClass {
+    /// The ASCII byte is written as-is.
+    Printable,
+    /// The byte is escaped with the shorthand for this letter, i.e. `n` for `\n`. The escape
+    /// character escapes itself.
+    ShortEscape(char),
+    /// The byte is escaped with `\x` (or `%XX` with `EscapeStyle::Percent`).
+    HexEscape,
+    /// The byte is not ASCII. It is written as-is if it is part of a valid UTF-8 char, otherwise
+    /// it is escaped with `\x`.
+    Utf8,
+}
+
+/// Classify how the encoder writes the byte `b`, ignoring its position in the input (see
+/// `Encoder::escape_edge_spaces`).
+pub(crate) fn classify_byte(encoder: &super::Encoder, b: u8) -> ByteClass {
+    match short_escape(encoder, b) {
+        // percent-encoding has no shorthands
+        Some(_) if encoder.escape_style == EscapeStyle::Percent => ByteClass::HexEscape,
+        Some(letter) => ByteClass::ShortEscape(letter),
+        None if b >= 0x80 && encoder.ascii_only => ByteClass::HexEscape,
+        None if b >= 0x80 => ByteClass::Utf8,
+        None if is_literal_ascii(encoder, b) => ByteClass::Printable,
+        None => ByteClass::HexEscape,
+    }
+}
+
+/// Escape a byte which is either non-printable or the escape character.
+pub(crate) fn escape_u8<W: fmt::Write>(
+    dst: &mut W,
+    encoder: &super::Encoder,
+    b: u8,
+) -> fmt::Result {
+    match short_escape(encoder, b) {
+        // percent-encoding has no shorthands
+        Some(_) if encoder.escape_style == EscapeStyle::Percent => escape_hex(dst, encoder, &[b]),
+        Some(letter) => {
+            dst.write_char(encoder.escape_char)?;
+            dst.write_char(letter)
         }
-        b'\n' => {
-            if encoder.encode_line_feed {
-                dst.push_str("\\n");
-            } else {
-                dst.push(b as char);
-            }
+        // not escaped in "pretty" mode
+        None if is_pretty_whitespace(b) && !is_escaped_whitespace(encoder, b) => {
+            dst.write_char(b as char)
         }
-        b'\r' => {
-            if encoder.encode_cariage {
-                dst.push_str("\\r");
-            } else {
-                dst.push(b as char);
+        None => escape_hex(dst, encoder, &[b]),
+    }
+}
+
+/// Whether the ASCII control character is written literally instead of being escaped.
+pub(crate) fn is_literal_control(encoder: &super::Encoder, b: u8) -> bool {
+    match b {
+        0x0B if !encoder.encode_vtab => true,
+        0x0C if !encoder.encode_form_feed => true,
+        0x00..=0x1F => encoder.literal_controls.contains(&b),
+        0x7F => !encoder.escape_del,
+        _ => false,
+    }
+}
+
+/// Whether the ASCII byte is written as-is (instead of being escaped) when encoding.
+pub(crate) fn is_literal_ascii(encoder: &super::Encoder, b: u8) -> bool {
+    match b {
+        // includes the escape character
+        _ if short_escape(encoder, b).is_some() => false,
+        _ if is_pretty_whitespace(b) => !is_escaped_whitespace(encoder, b),
+        0x20..=0x7E => true,
+        _ => is_literal_control(encoder, b),
+    }
+}
+
+/// The range of `v` between its leading and trailing spaces, if the encoder escapes them (see
+/// `Encoder::escape_edge_spaces`). Otherwise the whole of `v`.
+pub(crate) fn edge_spaces<T: Copy + PartialEq + From<u8>>(
+    encoder: &super::Encoder,
+    v: &[T],
+) -> (usize, usize) {
+    if !encoder.escape_edge_spaces {
+        return (0, v.len());
+    }
+    let space = T::from(b' ');
+    let start = v.iter().take_while(|c| **c == space).count();
+    let end = v.len() - v[start..].iter().rev().take_while(|c| **c == space).count();
+    (start, end)
+}
+
+/// Whitespace which is left as-is by a "pretty" `Encoder`.
+pub(crate) fn is_pretty_whitespace(b: u8) -> bool {
+    b == b'\t' || b == b'\n' || b == b'\r'
+}
+
+/// Whether the encoder escapes the whitespace, either as a shorthand or as `\x`.
+fn is_escaped_whitespace(encoder: &super::Encoder, b: u8) -> bool {
+    match b {
+        b'\t' => encoder.encode_tab,
+        b'\n' => encoder.encode_line_feed,
+        b'\r' => encoder.encode_cariage,
+        _ => false,
+    }
+}
+
+/// Escape the bytes as a single `\x` escape, written big-endian.
+pub(crate) fn escape_x<W: fmt::Write>(dst: &mut W, esc: char, bytes: &[u8]) -> fmt::Result {
+    dst.write_char(esc)?;
+    dst.write_char('x')?;
+    for b in bytes {
+        write!(dst, "{:0>2X}", b)?;
+    }
+    Ok(())
+}
+
+/// Escape the bytes in the encoder's style: as a single `\x` escape, or `%XX` for each byte.
+pub(crate) fn escape_hex<W: fmt::Write>(
+    dst: &mut W,
+    encoder: &super::Encoder,
+    bytes: &[u8],
+) -> fmt::Result {
+    match encoder.escape_style {
+        EscapeStyle::Backslash => escape_x(dst, encoder.escape_char, bytes),
+        EscapeStyle::Percent => {
+            for b in bytes {
+                write!(dst, "%{:0>2X}", b)?;
             }
+            Ok(())
         }
-        _ => write!(dst, r"\x{:0>2X}", b).unwrap(),
     }
 }
 
-pub(crate) fn escape_u16(dst: &mut String, c16: u16) {
-    write!(dst, r"\u{:0>6X}", c16).unwrap();
+/// Escape a `u16` value with `\u`, with four digits if the encoder uses short `\u` escapes.
+pub(crate) fn escape_u16<W: fmt::Write>(
+    dst: &mut W,
+    encoder: &super::Encoder,
+    c16: u16,
+) -> fmt::Result {
+    if encoder.short_u_escapes {
+        write!(dst, "{}u{:0>4X}", encoder.escape_char, c16)
+    } else {
+        write!(dst, "{}u{:0>6X}", encoder.escape_char, c16)
+    }
+}
+
+/// Escape the char with the brace form of a `\u` escape, i.e. `\u{1F600}`.
+pub(crate) fn escape_brace<W: fmt::Write>(dst: &mut W, esc: char, c: char) -> fmt::Result {
+    write!(dst, "{}u{{{:X}}}", esc, c as u32)
+}
+
+/// Whether `bytes` start with a line or paragraph separator which the encoder escapes.
+pub(crate) fn is_line_separator(encoder: &super::Encoder, bytes: &[u8]) -> bool {
+    encoder.escape_line_separators
+        && (bytes.starts_with(b"\xE2\x80\xA8") || bytes.starts_with(b"\xE2\x80\xA9"))
+}
+
+/// Escape a line or paragraph separator as `\u002028` or `\u002029`.
+pub(crate) fn escape_line_separator<W: fmt::Write>(
+    dst: &mut W,
+    encoder: &super::Encoder,
+    c: char,
+) -> fmt::Result {
+    if encoder.escape_style == EscapeStyle::Percent {
+        escape_hex(dst, encoder, c.encode_utf8(&mut [0; 4]).as_bytes())
+    } else {
+        escape_u16(dst, encoder, c as u16)
+    }
+}
+
+/// Whether the encoder escapes chars outside the basic multilingual plane.
+pub(crate) fn escapes_supplementary(encoder: &super::Encoder) -> bool {
+    encoder.escape_supplementary || encoder.escape_supplementary_long
+}
+
+/// Escape a char outside the basic multilingual plane, either as `\U0001F600` or `\u{1F600}`.
+pub(crate) fn escape_supplementary<W: fmt::Write>(
+    dst: &mut W,
+    encoder: &super::Encoder,
+    c: char,
+) -> fmt::Result {
+    if encoder.escape_style == EscapeStyle::Percent {
+        escape_hex(dst, encoder, c.encode_utf8(&mut [0; 4]).as_bytes())
+    } else if encoder.escape_supplementary_long {
+        write!(dst, "{}U{:0>8X}", encoder.escape_char, c as u32)
+    } else {
+        escape_brace(dst, encoder.escape_char, c)
+    }
 }
 
 #[cfg(test)]
@@ -88,6 +275,74 @@ mod tests {
         assert_eq!(expect_suplimental, got_suplimental);
     }
 
+    #[test]
+    fn sanity_classify_byte() {
+        let encoder = crate::Encoder::new();
+        assert_eq!(classify_byte(&encoder, b'a'), ByteClass::Printable);
+        assert_eq!(classify_byte(&encoder, b' '), ByteClass::Printable);
+        assert_eq!(classify_byte(&encoder, b'\\'), ByteClass::ShortEscape('\\'));
+        assert_eq!(classify_byte(&encoder, b'\n'), ByteClass::ShortEscape('n'));
+        assert_eq!(classify_byte(&encoder, 0x00), ByteClass::HexEscape);
+        assert_eq!(classify_byte(&encoder, 0x7F), ByteClass::HexEscape);
+        assert_eq!(classify_byte(&encoder, 0xC3), ByteClass::Utf8);
+
+        let encoder = crate::Encoder::pretty().with_ascii_only(true);
+        assert_eq!(classify_byte(&encoder, b'\n'), ByteClass::Printable);
+        assert_eq!(classify_byte(&encoder, 0xC3), ByteClass::HexEscape);
+        let encoder = encoder.with_escape_style(EscapeStyle::Percent);
+        assert_eq!(classify_byte(&encoder, b'%'), ByteClass::HexEscape);
+
+        // the classification is what the encoder does with the byte on its own
+        let encoder = crate::Encoder::new();
+        for b in 0..0x80 {
+            let encoded = crate::encode_u8::encode(&encoder, &[b]);
+            let expected = match classify_byte(&encoder, b) {
+                ByteClass::Printable => (b as char).to_string(),
+                ByteClass::ShortEscape(letter) => format!("\\{}", letter),
+                ByteClass::HexEscape => format!("\\x{:02X}", b),
+                ByteClass::Utf8 => unreachable!(),
+            };
+            assert_eq!(encoded, expected);
+        }
+    }
+
+    #[test]
+    fn sanity_from_hex() {
+        assert_eq!(from_hex(b"00"), Some(0));
+        assert_eq!(from_hex(b"fF"), Some(0xFF));
+        assert_eq!(from_hex(b"10FFFF"), Some(0x10_FFFF));
+        assert_eq!(from_hex(b"+F"), None);
+        assert_eq!(from_hex(b"0g"), None);
+        assert_eq!(from_hex("\u{A1}".as_bytes()), None);
+    }
+
+    #[test]
+    fn sanity_from_hex_matches_from_str_radix() {
+        let digits = b"0123456789abcdefABCDEFgG +-";
+        for a in digits {
+            for b in digits {
+                let pair = [*a, *b];
+                let s = std::str::from_utf8(&pair).unwrap();
+                // `from_str_radix` also accepts a sign, which is never part of an escape
+                let expected = match *a {
+                    b'+' | b'-' => None,
+                    _ => u32::from_str_radix(s, 16).ok(),
+                };
+                assert_eq!(from_hex(&pair), expected, "{:?}", s);
+            }
+        }
+        for s in &[
+            "000000", "10FFFF", "10ffff", "00d83D", "0001f6", "FFFFFFFF", "aBcDeF01",
+        ] {
+            assert_eq!(
+                from_hex(s.as_bytes()),
+                u32::from_str_radix(s, 16).ok(),
+                "{}",
+                s
+            );
+        }
+    }
+
     #[test]
     fn sanity_utf_conversion() {
         assert_conversions("foo bar", false);
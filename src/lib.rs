@@ -22,24 +22,107 @@
 //!
 //! - [`encode_u8`](fn.encode_u8.html) and [`decode_u8`](fn.decode_u8.html)
 //! - [`encode_u16`](fn.encode_u16.html) and [`decode_u16`](fn.decode_u16.html)
+//! - [`Encoder`](struct.Encoder.html) and [`Decoder`](struct.Decoder.html) to customize them
+//!
+//! To write STFU-8 directly into a formatter (i.e. with `println!`) use the
+//! [`Stfu8`](struct.Stfu8.html) and [`Stfu8Pretty`](struct.Stfu8Pretty.html) wrappers.
+//!
+//! [`Stfu8Bytes`](struct.Stfu8Bytes.html) can be parsed from STFU-8 with `str::parse`. With the
+//! `serde` feature enabled it can also be used to (de)serialize bytes as a STFU-8 encoded string.
+//!
+//! With the `futures` feature enabled,
+//! [`decode_u8_to_async_writer`](fn.decode_u8_to_async_writer.html) decodes into an
+//! `AsyncWrite`.
+//!
+//! With the `base64` feature enabled, [`encode_u8_base64`](fn.encode_u8_base64.html) and
+//! [`decode_u8_base64`](fn.decode_u8_base64.html) wrap STFU-8 in base64 for transport.
 //!
 //! Also see the [project README](https://github.com/vitiral/stfu8) and consider starring it!
 
 #![forbid(unsafe_code)]
+#![cfg_attr(test, allow(clippy::invisible_characters))]
+// the tests read the fields of `DecodeError` directly, which are deprecated for callers
+#![cfg_attr(test, allow(deprecated))]
 
+#[cfg(feature = "base64")]
+extern crate base64;
 #[cfg(test)]
 #[macro_use]
 extern crate pretty_assertions;
+#[cfg(feature = "futures")]
+extern crate futures;
+#[cfg(feature = "serde")]
+extern crate serde;
 
-use std::u16;
-use std::u8;
-
+mod adaptive;
+#[cfg(feature = "futures")]
+mod async_write;
+#[cfg(feature = "base64")]
+mod base64_impl;
+mod bytes;
+mod cstr;
 mod decode;
+mod display;
 mod encode_u16;
 mod encode_u8;
 mod helpers;
+mod mask;
+#[cfg(any(unix, windows))]
+mod os_str;
+mod profile;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod sink;
+mod spec;
+mod wrap;
+
+use std::collections::TryReserveError;
+use std::str;
+
+pub use adaptive::{decode_adaptive, encode_adaptive};
+#[cfg(feature = "futures")]
+pub use async_write::decode_u8_to_async_writer;
+#[cfg(feature = "base64")]
+pub use base64_impl::{decode_u8_base64, encode_u8_base64};
+pub use bytes::Stfu8Bytes;
+pub use cstr::{decode_to_cstring, encode_cstr};
+pub use decode::{DecodeError, DecodeErrorKind, DecodeU8Iter, DecodedItem};
+pub use display::{Stfu8, Stfu8Pretty};
+pub use encode_u8::{EncodeU8Iter, StreamEncoder};
+pub use helpers::ByteClass;
+pub use mask::EscapeMask;
+#[cfg(any(unix, windows))]
+pub use os_str::{decode_os_string, encode_os_str};
+pub use profile::decode_auto;
+pub use sink::{EscapeSink, IoSink};
+pub use spec::{EscapeSpec, SpecEntry};
+
+/// The most bytes of output a single byte of input can be encoded as by any `encode_u8*`
+/// function or [`Encoder`](struct.Encoder.html) method, i.e. the 4 bytes of `\xFF`.
+///
+/// So `v.len() * MAX_EXPANSION_U8` bytes always hold the encoding of `v`, which allows sizing a
+/// buffer without encoding. This does not count the line continuations added by
+/// [`Encoder::wrap_at`](struct.Encoder.html#structfield.wrap_at). The multiplication overflows
+/// for inputs longer than `usize::MAX / 4`, so use `checked_mul` for untrusted lengths.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let v = b"\xFF\xFE\x00";
+/// let capacity = v.len().checked_mul(stfu8::MAX_EXPANSION_U8).unwrap();
+/// assert!(stfu8::encoded_len_u8(v) <= capacity);
+/// # }
+/// ```
+pub const MAX_EXPANSION_U8: usize = 4;
 
-pub use decode::{DecodeError, DecodeErrorKind};
+/// The most bytes of output a single `u16` of input can be encoded as by any `encode_u16*`
+/// function or [`Encoder`](struct.Encoder.html) method, i.e. the 8 bytes of `\u00D800`.
+///
+/// Like [`MAX_EXPANSION_U8`](constant.MAX_EXPANSION_U8.html) this does not count line
+/// continuations, and the multiplication overflows for inputs longer than `usize::MAX / 8`.
+pub const MAX_EXPANSION_U16: usize = 8;
 
 /// Encode text as STFU-8, escaping all non-printable or non UTF-8 bytes.
 ///
@@ -63,6 +146,204 @@ pub fn encode_u8(v: &[u8]) -> String {
     encode_u8::encode(&encoder, v)
 }
 
+/// Encode a `str` as STFU-8, with the same output as [`encode_u8`](fn.encode_u8.html).
+///
+/// Since the text is known to be valid UTF-8 only the ASCII bytes need to be checked, which is
+/// faster than `encode_u8(s.as_bytes())`.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let encoded = stfu8::encode_str("foo\t¡\\bar\n");
+/// assert_eq!(encoded, r"foo\t¡\\bar\n");
+/// assert_eq!(encoded, stfu8::encode_u8("foo\t¡\\bar\n".as_bytes()));
+/// # }
+/// ```
+pub fn encode_str(s: &str) -> String {
+    let encoder = Encoder::new();
+    encode_u8::encode_str(&encoder, s)
+}
+
+/// Encode a single `char` as STFU-8, with the same output as [`encode_str`](fn.encode_str.html).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let mut out = String::from("a");
+/// out.push_str(&stfu8::encode_char('\n'));
+/// out.push_str(&stfu8::encode_char('\\'));
+/// out.push_str(&stfu8::encode_char('¡'));
+/// assert_eq!(out, r"a\n\\¡");
+/// # }
+/// ```
+pub fn encode_char(c: char) -> String {
+    Encoder::new().encode_char(c)
+}
+
+/// Encode the chars as STFU-8, with the same output as [`encode_str`](fn.encode_str.html) of the
+/// collected string.
+///
+/// This is useful for text produced by an iterator, such as a parser, since it doesn't need to
+/// be collected (or validated) first.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let chars = "foo\t¡\\bar\n".chars().filter(|c| *c != 'o');
+/// assert_eq!(stfu8::encode_chars(chars), r"f\t¡\\bar\n");
+/// # }
+/// ```
+pub fn encode_chars<I: IntoIterator<Item = char>>(chars: I) -> String {
+    Encoder::new().encode_chars(chars)
+}
+
+/// Encode text as STFU-8, escaping the bytes marked in `mask` even when they don't need to be.
+///
+/// Together with [`decode_u8_masked`](fn.decode_u8_masked.html) this keeps the escapes chosen by
+/// the author of a file, i.e. a `\x41` is not re-encoded as `A`. Escaped bytes use their
+/// shorthand (like `\n`) if there is one, otherwise `\x`.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let (bytes, mask) = stfu8::decode_u8_masked(r"foo\x41\nbar\xFF").unwrap();
+/// assert_eq!(bytes, b"fooA\nbar\xFF");
+/// assert_eq!(stfu8::encode_u8(&bytes), r"fooA\nbar\xFF");
+/// assert_eq!(stfu8::encode_u8_masked(&bytes, &mask), r"foo\x41\nbar\xFF");
+/// # }
+/// ```
+pub fn encode_u8_masked(v: &[u8], mask: &EscapeMask) -> String {
+    let encoder = Encoder::new();
+    encode_u8::encode_masked(&encoder, v, mask)
+}
+
+/// The exact length in bytes of [`encode_u8`](fn.encode_u8.html)`(v)`, computed without
+/// allocating the output.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(stfu8::encoded_len_u8(b"foo\xFF\nbar"), r"foo\xFF\nbar".len());
+/// # }
+/// ```
+pub fn encoded_len_u8(v: &[u8]) -> usize {
+    let encoder = Encoder::new();
+    encode_u8::encoded_len(&encoder, v)
+}
+
+/// Whether [`encode_u8`](fn.encode_u8.html)`(v)` would differ from `v`, i.e. whether `v` has
+/// any byte which is escaped.
+///
+/// This is cheaper than encoding, since it stops at the first escaped byte. Note that the escape
+/// character (`\`) is escaped even though it is printable.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert!(!stfu8::needs_encoding("plain text ¡".as_bytes()));
+/// assert!(stfu8::needs_encoding(b"foo\nbar"));
+/// assert!(stfu8::needs_encoding(b"C:\\foo"));
+/// assert!(stfu8::needs_encoding(b"\xFF"));
+///
+/// // a "pretty" encoder writes line feeds as-is
+/// assert!(!stfu8::Encoder::pretty().needs_encoding(b"foo\nbar"));
+/// # }
+/// ```
+pub fn needs_encoding(v: &[u8]) -> bool {
+    Encoder::new().needs_encoding(v)
+}
+
+/// Encode text as STFU-8, also escaping every byte for which `should_escape` returns `true`.
+///
+/// This allows escaping more characters than usual, i.e. quotes when embedding the output in a
+/// quoted string. The bytes are escaped like the bytes of an [`EscapeMask`](struct.EscapeMask.html),
+/// so a multibyte char is only escaped if one of its bytes is.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let encoded = stfu8::encode_u8_with(b"say \"\xFF\" or '\xC2\xA1'", |b| b == b'"' || b == b'\'');
+/// assert_eq!(encoded, r"say \x22\xFF\x22 or \x27¡\x27");
+/// assert_eq!(stfu8::decode_u8(&encoded).unwrap(), b"say \"\xFF\" or '\xC2\xA1'");
+/// # }
+/// ```
+pub fn encode_u8_with<F: Fn(u8) -> bool>(v: &[u8], should_escape: F) -> String {
+    Encoder::new().encode_u8_with(v, should_escape)
+}
+
+/// Lazily encode text as STFU-8, yielding the chars of [`encode_u8`](fn.encode_u8.html).
+///
+/// Nothing is allocated per call besides a small buffer for the escape currently being yielded.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let mut out = String::from("data: ");
+/// out.extend(stfu8::encode_u8_iter(b"foo\xFF\nbar"));
+/// assert_eq!(out, r"data: foo\xFF\nbar");
+/// # }
+/// ```
+pub fn encode_u8_iter(v: &[u8]) -> EncodeU8Iter<'_> {
+    EncodeU8Iter::new(Encoder::new(), v)
+}
+
+/// The same as [`encode_u8`](fn.encode_u8.html), but returning an error instead of aborting if
+/// the output can't be allocated.
+///
+/// All of the output is reserved with `String::try_reserve`, so that a service can reject a huge
+/// input under memory pressure instead of crashing.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(stfu8::encode_u8_try(b"foo\xFF\nbar").unwrap(), r"foo\xFF\nbar");
+/// # }
+/// ```
+pub fn encode_u8_try(v: &[u8]) -> Result<String, TryReserveError> {
+    encode_u8::try_encode(&Encoder::new(), v)
+}
+
+/// The same as [`encode_u8`](fn.encode_u8.html), but reserving `extra` bytes of capacity beyond
+/// `v.len()` in the output.
+///
+/// `encode_u8` guesses that only a few bytes need escaping. Escaping a byte as `\xXX` takes four
+/// bytes, so mostly binary data can grow to about four times its length: pass
+/// `extra = v.len() * 3` to avoid reallocating while encoding it.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let binary = [0xFF; 64];
+/// let encoded = stfu8::encode_u8_with_capacity(&binary, binary.len() * 3);
+/// assert_eq!(encoded, stfu8::encode_u8(&binary));
+/// assert!(encoded.capacity() >= 256);
+/// # }
+/// ```
+pub fn encode_u8_with_capacity(v: &[u8], extra: usize) -> String {
+    let encoder = Encoder::new();
+    encode_u8::encode_with_capacity(&encoder, v, v.len().saturating_add(extra))
+}
+
 /// Encode text as STFU-8, escaping all non-printable or non UTF-8 bytes EXCEPT:
 ///
 /// - `\t`: tab
@@ -175,9 +456,43 @@ pub fn encode_u16_pretty(v: &[u16]) -> String {
     encode_u16::encode(&encoder, v)
 }
 
-/// Just used for error messages
-fn escape_u32(c32: u32) -> String {
-    format!(r"\u{:0>6X}", c32)
+/// Encode little-endian UTF-16 bytes (i.e. a UTF-16 file from windows) as STFU-8, the same as
+/// [`encode_u16`](fn.encode_u16.html) of the units they contain.
+///
+/// If `bytes` has an odd length the final byte is not part of any unit. So that it isn't lost it
+/// is written as a `\x` escape at the end of the output, which
+/// [`decode_u16`](fn.decode_u16.html) decodes as a final unit with that value.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(stfu8::encode_utf16le(b"f\x00o\x00\x00\xD8"), r"fo\u00D800");
+/// assert_eq!(stfu8::encode_utf16le(b"f\x00o\x00\xFF"), r"fo\xFF");
+/// # }
+/// ```
+pub fn encode_utf16le(bytes: &[u8]) -> String {
+    let encoder = Encoder::new();
+    encode_u16::encode_bytes(&encoder, bytes, u16::from_le_bytes)
+}
+
+/// Encode big-endian UTF-16 bytes as STFU-8, the same as [`encode_u16`](fn.encode_u16.html) of
+/// the units they contain.
+///
+/// A final odd byte is handled like in [`encode_utf16le`](fn.encode_utf16le.html).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(stfu8::encode_utf16be(b"\x00f\x00o\xD8\x00"), r"fo\u00D800");
+/// # }
+/// ```
+pub fn encode_utf16be(bytes: &[u8]) -> String {
+    let encoder = Encoder::new();
+    encode_u16::encode_bytes(&encoder, bytes, u16::from_be_bytes)
 }
 
 /// Decode a UTF-8 string containing encoded STFU-8 into binary.
@@ -186,6 +501,11 @@ fn escape_u32(c32: u32) -> String {
 ///
 /// - [`encode_u8`](fn.encode_u8.html)
 /// - [`encode_u8_pretty`](fn.encode_u8_pretty.html)
+/// - [`encode_str`](fn.encode_str.html)
+///
+/// If the decoded data is expected to be text, use
+/// [`decode_u8_as_str`](fn.decode_u8_as_str.html) to get a `String` without validating it
+/// separately.
 ///
 /// # Examples
 /// ```rust
@@ -205,118 +525,765 @@ fn escape_u32(c32: u32) -> String {
 /// # }
 /// ```
 pub fn decode_u8(s: &str) -> Result<Vec<u8>, DecodeError> {
-    let mut out: Vec<u8> = Vec::new();
-    {
-        let f = |val: decode::PushGeneric| -> Result<(), DecodeError> {
-            match val {
-                decode::PushGeneric::Value { val, start } => {
-                    if val > u8::MAX as u32 {
-                        Err(DecodeError {
-                            index: start,
-                            kind: DecodeErrorKind::InvalidValue,
-                            mat: escape_u32(val),
-                        })
-                    } else {
-                        out.push(val as u8);
-                        Ok(())
-                    }
-                }
-                decode::PushGeneric::String(s) => {
-                    out.extend_from_slice(s.as_bytes());
-                    Ok(())
-                }
-            }
-        };
-        decode::decode_generic(f, s)?;
-    }
-    Ok(out)
+    Decoder::new().decode_u8(s)
 }
 
-/// Decode a UTF-8 string containing encoded STFU-8 into a `Vec<u16>`.
+/// Decode STFU-8 into a `Box<[u8]>`, for long-lived storage without excess capacity.
 ///
-/// Can decode the output of these functions:
+/// The input is decoded twice: once to find the exact length, so the output is allocated once
+/// at that size (instead of shrinking the `Vec` of [`decode_u8`](fn.decode_u8.html)).
 ///
-/// - [`encode_u16`](fn.encode_u16.html)
-/// - [`encode_u16_pretty`](fn.encode_u16_pretty.html)
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let boxed = stfu8::decode_u8_boxed(r"foo\xFF\nbar").unwrap();
+/// assert_eq!(&*boxed, b"foo\xFF\nbar");
+/// # }
+/// ```
+pub fn decode_u8_boxed(s: &str) -> Result<Box<[u8]>, DecodeError> {
+    Decoder::new().decode_u8_boxed(s)
+}
+
+/// Decode STFU-8 into binary lazily, yielding one byte at a time.
+///
+/// The bytes are the same as [`decode_u8`](fn.decode_u8.html), but nothing is collected: only
+/// the expansion of the current escape (i.e. the UTF-8 of a `\u` escape) is buffered. The
+/// first error is yielded in place of the byte, after which the iterator ends.
 ///
 /// # Examples
 /// ```rust
 /// # extern crate stfu8;
 ///
 /// # fn main() {
-/// let mut ill: Vec<u16> = "fooÿ\nbar"
-///     .encode_utf16()
-///     .collect();
+/// let decoded: Result<Vec<u8>, _> = stfu8::decode_u8_iter(r"foo\xFF\u0000A2").collect();
+/// assert_eq!(decoded.unwrap(), b"foo\xFF\xC2\xA2");
 ///
-/// // Make it ill formed UTF-16
-/// ill.push(0xD800);       // surrogate pair lead
-/// ill.push(b' ' as u16);  // NOT a trail
-/// ill.push(0xDEED);       // Trail... with no lead
-/// ill.push(b' ' as u16);
-/// ill.push(0xDABA);       // lead... but end of str
-/// let encoded = stfu8::encode_u16(ill.as_slice());
+/// let mut iter = stfu8::decode_u8_iter(r"a\qb");
+/// assert_eq!(iter.next(), Some(Ok(b'a')));
+/// let err = iter.next().unwrap().unwrap_err();
+/// assert_eq!(*err.kind(), stfu8::DecodeErrorKind::UnescapedSlash);
+/// assert_eq!(iter.next(), None);
+/// # }
+/// ```
+pub fn decode_u8_iter(s: &str) -> DecodeU8Iter<'_> {
+    DecodeU8Iter::new(Decoder::new(), s)
+}
+
+/// Decode the STFU-8 text in `buf` into binary in place, like [`decode_u8`](fn.decode_u8.html)
+/// but without allocating the output.
 ///
-/// // Note that 0xFF is the valid character "ÿ"
-/// // and the ill-formed characters are escaped.
-/// assert_eq!(
-///     encoded,
-///     r"fooÿ\nbar\u00D800 \u00DEED \u00DABA"
-/// );
+/// The decoded bytes are never longer than the text, so they are written over it and `buf` is
+/// truncated to their length. If `buf` is not UTF-8 it is a
+/// [`NotUtf8`](enum.DecodeErrorKind.html#variant.NotUtf8) error. On any error `buf` is left
+/// unchanged.
 ///
-/// assert_eq!(ill, stfu8::decode_u16(&encoded).unwrap());
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let mut buf = br"foo\xFF\nbar".to_vec();
+/// stfu8::decode_u8_in_place(&mut buf).unwrap();
+/// assert_eq!(buf, b"foo\xFF\nbar");
+///
+/// let mut buf = br"foo\bar".to_vec();
+/// let err = stfu8::decode_u8_in_place(&mut buf).unwrap_err();
+/// assert_eq!(*err.kind(), stfu8::DecodeErrorKind::UnescapedSlash);
+/// assert_eq!(buf, br"foo\bar");
 /// # }
 /// ```
-pub fn decode_u16(s: &str) -> Result<Vec<u16>, DecodeError> {
-    let mut out: Vec<u16> = Vec::new();
-    {
-        let f = |val: decode::PushGeneric| -> Result<(), DecodeError> {
-            match val {
-                decode::PushGeneric::Value { val, start } => {
-                    if val > u16::MAX as u32 {
-                        Err(DecodeError {
-                            index: start,
-                            kind: DecodeErrorKind::InvalidValue,
-                            mat: escape_u32(val),
-                        })
-                    } else {
-                        out.push(val as u16);
-                        Ok(())
-                    }
-                }
-                decode::PushGeneric::String(s) => {
-                    for c in s.chars() {
-                        let mut buf = [0u16; 2];
-                        out.extend_from_slice(c.encode_utf16(&mut buf));
-                    }
-                    Ok(())
-                }
-            }
-        };
-        decode::decode_generic(f, s)?;
-    }
-    Ok(out)
+pub fn decode_u8_in_place(buf: &mut Vec<u8>) -> Result<(), DecodeError> {
+    Decoder::new().decode_u8_in_place(buf)
 }
 
-// NOT YET STABILIZED
+/// Decode the longest valid prefix of STFU-8 into binary, returning it and the number of bytes
+/// of `s` it was decoded from.
+///
+/// Decoding stops cleanly at the start of the first escape which [`decode_u8`](fn.decode_u8.html)
+/// would return an error for, which is not consumed:
+///
+/// - an escape character followed by anything but a known escape, i.e. `\q` or `\|`
+/// - an escape character at the end of `s`
+/// - a `\x` or `\u` escape with too few or invalid hex digits
+/// - a `\u` escape of a value which is not a byte or a unicode scalar value (a surrogate)
+///
+/// Unescaped text is always valid, so if there is no such escape all of `s` is consumed. This
+/// allows framing data with a delimiter that isn't valid STFU-8, such as `\|`.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let s = r"foo\xFF\n\|bar";
+/// let (decoded, consumed) = stfu8::decode_u8_prefix(s);
+/// assert_eq!(decoded, b"foo\xFF\n");
+/// assert_eq!(&s[consumed..], r"\|bar");
+///
+/// assert_eq!(stfu8::decode_u8_prefix(r"foo\t"), (b"foo\t".to_vec(), 5));
+/// # }
+/// ```
+pub fn decode_u8_prefix(s: &str) -> (Vec<u8>, usize) {
+    Decoder::new().decode_u8_prefix(s)
+}
 
-/// Settings for encoding binary data.
+/// Decode STFU-8 into binary like [`decode_u8`](fn.decode_u8.html), but continue past errors
+/// and return all of them.
+///
+/// Each invalid escape is skipped (it is not part of the output) and decoding continues after
+/// it, so the bytes are a best-effort decoding of the input. The `index` of every error is into
+/// the whole of `s`.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
 ///
-/// TODO: make this public eventually
-pub(crate) struct Encoder {
-    pub(crate) encode_tab: bool,       // \t \x09
-    pub(crate) encode_line_feed: bool, // \n \x0A
-    pub(crate) encode_cariage: bool,   // \r \x0D
+/// # fn main() {
+/// let (decoded, errors) = stfu8::decode_u8_collect_errors(r"a\q b\xZZ c\");
+/// assert_eq!(decoded, b"a b c");
+/// let kinds: Vec<_> = errors.iter().map(|e| (e.index(), e.kind().clone())).collect();
+/// assert_eq!(
+///     kinds,
+///     vec![
+///         (1, stfu8::DecodeErrorKind::UnescapedSlash),
+///         (5, stfu8::DecodeErrorKind::InvalidHexDigit),
+///         (11, stfu8::DecodeErrorKind::TrailingBackslash),
+///     ]
+/// );
+/// # }
+/// ```
+pub fn decode_u8_collect_errors(s: &str) -> (Vec<u8>, Vec<DecodeError>) {
+    Decoder::new().decode_u8_collect_errors(s)
 }
 
-impl Encoder {
-    /// Create a new "non pretty" `Encoder`.
-    ///
-    /// ALL non-printable characters will be escaped
+/// Decode STFU-8 which was encoded `times` times, i.e. `\\x1B` for a `\x1B` byte encoded twice.
+///
+/// Every decode but the last must result in UTF-8 so that it can be decoded again, otherwise it
+/// is a [`NotUtf8`](enum.DecodeErrorKind.html#variant.NotUtf8) error. See
+/// [`Decoder::decode_u8_nested`](struct.Decoder.html#method.decode_u8_nested). Unlike that
+/// method it decodes all `times` levels, it is not capped by the default
+/// [`max_nesting`](struct.Decoder.html#structfield.max_nesting).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(stfu8::decode_u8_n(r"foo\\x1B", 2).unwrap(), b"foo\x1B");
+/// assert_eq!(stfu8::decode_u8_n(r"foo\\x1B", 1).unwrap(), br"foo\x1B");
+///
+/// // `\xFF` is not UTF-8, so it can't be decoded again
+/// let err = stfu8::decode_u8_n(r"\xFF\\x1B", 2).unwrap_err();
+/// assert_eq!(*err.kind(), stfu8::DecodeErrorKind::NotUtf8);
+/// # }
+/// ```
+pub fn decode_u8_n(s: &str, times: usize) -> Result<Vec<u8>, DecodeError> {
+    let decoder = Decoder {
+        max_nesting: times,
+        ..Decoder::new()
+    };
+    decoder.decode_u8_nested(s, times)
+}
+
+/// Decode STFU-8 into binary like [`decode_u8`](fn.decode_u8.html), also returning which of the
+/// bytes were escaped.
+///
+/// See [`encode_u8_masked`](fn.encode_u8_masked.html).
+pub fn decode_u8_masked(s: &str) -> Result<(Vec<u8>, EscapeMask), DecodeError> {
+    mask::decode(&Decoder::new(), s)
+}
+
+/// Decode STFU-8 into a custom sink, which is called with each [`DecodedItem`](enum.DecodedItem.html)
+/// in order.
+///
+/// Errors returned by the sink are returned as-is (after computing their line and column), and
+/// stop the decoding.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+/// use stfu8::{DecodeError, DecodedItem};
+///
+/// # fn main() {
+/// // decode into a `String`, rejecting escaped bytes which aren't ASCII
+/// let mut out = String::new();
+/// let result = stfu8::decode_into(r"foo\x41\u{1F600}\xFF", |item| {
+///     match item {
+///         DecodedItem::Str(s) => out.push_str(s),
+///         DecodedItem::Char(c) => out.push(c),
+///         DecodedItem::Value { value, .. } if value < 0x80 => out.push(value as u8 as char),
+///         DecodedItem::Value { index, .. } => return Err(DecodeError::invalid_value(index)),
+///         _ => unreachable!(),
+///     }
+///     Ok(())
+/// });
+/// assert_eq!(out, "fooA\u{1F600}");
+///
+/// let err = result.unwrap_err();
+/// assert_eq!(err.index(), 16);
+/// assert_eq!(err.matched(), r"\xFF");
+/// # }
+/// ```
+pub fn decode_into<F>(s: &str, f: F) -> Result<(), DecodeError>
+where
+    F: FnMut(DecodedItem) -> Result<(), DecodeError>,
+{
+    decode::decode_into(&Decoder::new(), s, f)
+}
+
+/// Decode a UTF-8 string containing encoded STFU-8 into a `Vec<u16>`.
+///
+/// Can decode the output of these functions:
+///
+/// - [`encode_u16`](fn.encode_u16.html)
+/// - [`encode_u16_pretty`](fn.encode_u16_pretty.html)
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let mut ill: Vec<u16> = "fooÿ\nbar"
+///     .encode_utf16()
+///     .collect();
+///
+/// // Make it ill formed UTF-16
+/// ill.push(0xD800);       // surrogate pair lead
+/// ill.push(b' ' as u16);  // NOT a trail
+/// ill.push(0xDEED);       // Trail... with no lead
+/// ill.push(b' ' as u16);
+/// ill.push(0xDABA);       // lead... but end of str
+/// let encoded = stfu8::encode_u16(ill.as_slice());
+///
+/// // Note that 0xFF is the valid character "ÿ"
+/// // and the ill-formed characters are escaped.
+/// assert_eq!(
+///     encoded,
+///     r"fooÿ\nbar\u00D800 \u00DEED \u00DABA"
+/// );
+///
+/// assert_eq!(ill, stfu8::decode_u16(&encoded).unwrap());
+/// # }
+/// ```
+pub fn decode_u16(s: &str) -> Result<Vec<u16>, DecodeError> {
+    Decoder::new().decode_u16(s)
+}
+
+/// Decode a UTF-8 string containing one STFU-8 record per line into a `Vec<u8>` per record.
+///
+/// Records are separated by a raw line feed (`\n`) or a raw carriage return and line feed
+/// (`\r\n`), which may be mixed. The separators are not part of the decoded records and a final
+/// separator at the end of the input does not start a new record, the same as `str::lines`.
+/// Escaped `\n` and `\r` are part of their record.
+///
+/// The `index` of an error is into the whole input.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let records = stfu8::decode_u8_records("foo\\n\r\nbar\\xFF\n").unwrap();
+/// assert_eq!(records, vec![b"foo\n".to_vec(), b"bar\xFF".to_vec()]);
+/// # }
+/// ```
+pub fn decode_u8_records(s: &str) -> Result<Vec<Vec<u8>>, DecodeError> {
+    let decoder = Decoder::new();
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < s.len() {
+        let end = match s[offset..].find('\n') {
+            Some(i) => offset + i,
+            None => s.len(),
+        };
+        let line = &s[offset..end];
+        let record = line.strip_suffix('\r').unwrap_or(line);
+        let decoded = decoder
+            .decode_u8(record)
+            .map_err(|err| err.offset(offset).locate(s))?;
+        records.push(decoded);
+        offset = end + 1;
+    }
+    Ok(records)
+}
+
+/// Decode a UTF-8 string containing encoded STFU-8 into a `String`, replacing any decoded bytes
+/// which are not valid UTF-8 with `U+FFFD` (the replacement character `�`).
+///
+/// This is the same as `String::from_utf8_lossy(&decode_u8(s)?)`, but done in one pass. Only
+/// malformed escapes are an error: escaped values which are not valid UTF-8 (or which don't even
+/// fit in a byte) are replaced.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(
+///     stfu8::decode_to_lossy_string(r"foo\xFF\xC2\xA1bar").unwrap(),
+///     "foo\u{FFFD}¡bar"
+/// );
+/// assert!(stfu8::decode_to_lossy_string(r"foo\bar").is_err());
+/// # }
+/// ```
+pub fn decode_to_lossy_string(s: &str) -> Result<String, DecodeError> {
+    let mut out = String::with_capacity(s.len());
+    // escaped bytes which may (or may not) form valid UTF-8
+    let mut pending: Vec<u8> = Vec::new();
+    {
+        let f = |val: decode::PushGeneric| -> Result<(), DecodeError> {
+            match val {
+                decode::PushGeneric::Value { val, .. } => {
+                    if val > u8::MAX as u32 {
+                        out.push_str(&String::from_utf8_lossy(&pending));
+                        out.push(char::REPLACEMENT_CHARACTER);
+                        pending.clear();
+                    } else {
+                        pending.push(val as u8);
+                    }
+                }
+                decode::PushGeneric::String(s) => {
+                    out.push_str(&String::from_utf8_lossy(&pending));
+                    out.push_str(s);
+                    pending.clear();
+                }
+                decode::PushGeneric::Char(c) => {
+                    out.push_str(&String::from_utf8_lossy(&pending));
+                    out.push(c);
+                    pending.clear();
+                }
+                decode::PushGeneric::Bytes { bytes, .. } => pending.extend_from_slice(bytes),
+            }
+            Ok(())
+        };
+        decode::decode_generic(&Decoder::new(), f, s)?;
+    }
+    out.push_str(&String::from_utf8_lossy(&pending));
+    Ok(out)
+}
+
+/// Decode a UTF-8 string containing encoded STFU-8 into a `Vec<u8>`, also returning the number
+/// of unicode scalars (`char`s) the decoded bytes represent.
+///
+/// If the decoded bytes are valid UTF-8 the count is `str::from_utf8(&bytes)?.chars().count()`.
+/// Otherwise each invalid sequence counts as a single char, i.e. the count is the number of
+/// `char`s in [`decode_to_lossy_string`](fn.decode_to_lossy_string.html).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let (bytes, count) = stfu8::decode_u8_counted(r"¡foo\xC2\xA2\n").unwrap();
+/// assert_eq!(bytes, "¡foo¢\n".as_bytes());
+/// assert_eq!(count, 6);
+///
+/// // `\xFF` is not UTF-8 and counts as one char
+/// let (_, count) = stfu8::decode_u8_counted(r"\xFFfoo").unwrap();
+/// assert_eq!(count, 4);
+/// # }
+/// ```
+pub fn decode_u8_counted(s: &str) -> Result<(Vec<u8>, usize), DecodeError> {
+    let mut out: Vec<u8> = Vec::with_capacity(s.len());
+    let mut count = 0;
+    // start of the escaped bytes in `out` which haven't been counted yet
+    let mut pending = 0;
+    {
+        let f = |val: decode::PushGeneric| -> Result<(), DecodeError> {
+            match val {
+                decode::PushGeneric::Value { val, start } => {
+                    out.push(decode::value_u8(start, val)?)
+                }
+                decode::PushGeneric::Bytes { bytes, .. } => out.extend_from_slice(bytes),
+                decode::PushGeneric::String(s) => {
+                    count += count_lossy(&out[pending..]) + s.chars().count();
+                    out.extend_from_slice(s.as_bytes());
+                    pending = out.len();
+                }
+                decode::PushGeneric::Char(c) => {
+                    count += count_lossy(&out[pending..]) + 1;
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    pending = out.len();
+                }
+            }
+            Ok(())
+        };
+        decode::decode_generic(&Decoder::new(), f, s)?;
+    }
+    count += count_lossy(&out[pending..]);
+    Ok((out, count))
+}
+
+/// The number of chars in the bytes, counting each invalid sequence as a single char.
+fn count_lossy(bytes: &[u8]) -> usize {
+    String::from_utf8_lossy(bytes).chars().count()
+}
+
+/// Decode a UTF-8 string containing encoded STFU-8 into a `String`.
+///
+/// This is the same as `String::from_utf8(decode_u8(s)?)`, but without the separate validation
+/// pass over the whole output: only the escaped bytes are validated. If they do not form valid
+/// UTF-8 this is a [`NotUtf8`](enum.DecodeErrorKind.html#variant.NotUtf8) error.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(
+///     stfu8::decode_u8_as_str(r"foo\xC2\xA1\nbar").unwrap(),
+///     "foo¡\nbar"
+/// );
+///
+/// let err = stfu8::decode_u8_as_str(r"foo\xFFbar").unwrap_err();
+/// assert_eq!(*err.kind(), stfu8::DecodeErrorKind::NotUtf8);
+/// assert_eq!(err.index(), 3);
+/// # }
+/// ```
+pub fn decode_u8_as_str(s: &str) -> Result<String, DecodeError> {
+    decode_as_str(&Decoder::new(), s)
+}
+
+/// Decode STFU-8 into a `String` using the `decoder`, see
+/// [`decode_u8_as_str`](fn.decode_u8_as_str.html).
+fn decode_as_str(decoder: &Decoder, s: &str) -> Result<String, DecodeError> {
+    let esc = decoder.escape_char;
+    let mut out = String::with_capacity(s.len());
+    // escaped bytes and the index of their escape
+    let mut pending: Vec<u8> = Vec::new();
+    let mut starts: Vec<usize> = Vec::new();
+    {
+        let f = |val: decode::PushGeneric| -> Result<(), DecodeError> {
+            match val {
+                decode::PushGeneric::Value { val, start } => {
+                    pending.push(decode::value_u8(start, val)?);
+                    starts.push(start);
+                }
+                decode::PushGeneric::String(s) => {
+                    push_utf8(&mut out, &mut pending, &mut starts, esc)?;
+                    out.push_str(s);
+                }
+                decode::PushGeneric::Char(c) => {
+                    push_utf8(&mut out, &mut pending, &mut starts, esc)?;
+                    out.push(c);
+                }
+                decode::PushGeneric::Bytes {
+                    starts: byte_starts,
+                    bytes,
+                } => {
+                    pending.extend_from_slice(bytes);
+                    starts.extend_from_slice(byte_starts);
+                }
+            }
+            Ok(())
+        };
+        decode::decode_generic(decoder, f, s)?;
+    }
+    push_utf8(&mut out, &mut pending, &mut starts, esc).map_err(|err| err.locate(s))?;
+    Ok(out)
+}
+
+/// Push the escaped bytes to `out` if they are valid UTF-8, otherwise match the invalid bytes
+/// escaped with `esc`.
+fn push_utf8(
+    out: &mut String,
+    pending: &mut Vec<u8>,
+    starts: &mut Vec<usize>,
+    esc: char,
+) -> Result<(), DecodeError> {
+    match str::from_utf8(pending) {
+        Ok(valid) => out.push_str(valid),
+        Err(err) => {
+            let index = err.valid_up_to();
+            let invalid = match err.error_len() {
+                Some(len) => &pending[index..(index + len)],
+                None => &pending[index..],
+            };
+            let mut mat = String::new();
+            for b in invalid {
+                helpers::escape_x(&mut mat, esc, &[*b]).unwrap();
+            }
+            return Err(DecodeError::new(
+                DecodeErrorKind::NotUtf8,
+                starts[index],
+                mat,
+            ));
+        }
+    }
+    pending.clear();
+    starts.clear();
+    Ok(())
+}
+
+/// Validate that the string is STFU-8 which can be decoded by [`decode_u8`](fn.decode_u8.html),
+/// without decoding it.
+///
+/// Also check out:
+///
+/// - [`is_valid_stfu8`](fn.is_valid_stfu8.html)
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert!(stfu8::validate_stfu8(r"foo\xFF\nbar").is_ok());
+///
+/// let err = stfu8::validate_stfu8(r"foo\bar").unwrap_err();
+/// assert_eq!(*err.kind(), stfu8::DecodeErrorKind::UnescapedSlash);
+/// assert_eq!(err.index(), 3);
+/// # }
+/// ```
+pub fn validate_stfu8(s: &str) -> Result<(), DecodeError> {
+    decode::decode_generic(&Decoder::new(), |val| decode::check_u8(&val), s)
+}
+
+/// Return the length in bytes of the longest run of `s` without any escapes, validating it like
+/// [`validate_stfu8`](fn.validate_stfu8.html).
+///
+/// This is the longest part of the input which decodes to itself, i.e. which could be borrowed
+/// instead of copied.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert_eq!(stfu8::max_passthrough_run(r"foo\nfoobar\xFFbaz").unwrap(), 6);
+/// assert_eq!(stfu8::max_passthrough_run(r"\n\t").unwrap(), 0);
+/// assert!(stfu8::max_passthrough_run(r"foo\bar").is_err());
+/// # }
+/// ```
+pub fn max_passthrough_run(s: &str) -> Result<usize, DecodeError> {
+    let mut max = 0;
+    {
+        let f = |val: decode::PushGeneric| -> Result<(), DecodeError> {
+            match val {
+                decode::PushGeneric::String(s) => {
+                    max = max.max(s.len());
+                    Ok(())
+                }
+                val => decode::check_u8(&val),
+            }
+        };
+        decode::decode_generic(&Decoder::new(), f, s)?;
+    }
+    Ok(max)
+}
+
+/// Classify how [`encode_u8`](fn.encode_u8.html) writes the byte `b`: as-is, with a shorthand
+/// escape such as `\n`, or with a `\x` escape.
+///
+/// See [`Encoder::classify_byte`](struct.Encoder.html#method.classify_byte) for other settings.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// use stfu8::ByteClass;
+///
+/// assert_eq!(stfu8::classify_byte(b'a'), ByteClass::Printable);
+/// assert_eq!(stfu8::classify_byte(b'\t'), ByteClass::ShortEscape('t'));
+/// assert_eq!(stfu8::classify_byte(b'\\'), ByteClass::ShortEscape('\\'));
+/// assert_eq!(stfu8::classify_byte(0x1B), ByteClass::HexEscape);
+/// assert_eq!(stfu8::classify_byte(0xFF), ByteClass::Utf8);
+/// # }
+/// ```
+pub fn classify_byte(b: u8) -> ByteClass {
+    Encoder::new().classify_byte(b)
+}
+
+/// Return whether the string is STFU-8 which can be decoded by [`decode_u8`](fn.decode_u8.html).
+///
+/// This is cheaper than `decode_u8(s).is_ok()` since nothing is decoded.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// assert!(stfu8::is_valid_stfu8(r"foo\xFF\nbar"));
+/// assert!(!stfu8::is_valid_stfu8(r"foo\bar"));
+/// assert!(!stfu8::is_valid_stfu8(r"foo\xF"));
+/// # }
+/// ```
+pub fn is_valid_stfu8(s: &str) -> bool {
+    validate_stfu8(s).is_ok()
+}
+
+/// The family of escapes written by an [`Encoder`](struct.Encoder.html) and read by a
+/// [`Decoder`](struct.Decoder.html).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// use stfu8::EscapeStyle;
+///
+/// let encoder = stfu8::Encoder::new().with_escape_style(EscapeStyle::Percent);
+/// assert_eq!(encoder.encode_u8(b"100%\n\xFF"), "100%25%0A%FF");
+///
+/// let mut decoder = stfu8::Decoder::new();
+/// decoder.escape_style = EscapeStyle::Percent;
+/// decoder.escape_char = '%';
+/// assert_eq!(decoder.decode_u8("100%25%0A%FF").unwrap(), b"100%\n\xFF");
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeStyle {
+    /// The escapes described in the [crate documentation](index.html), i.e. `\xFF`, `\n` and
+    /// `\u00D800`, started by the `escape_char`.
+    Backslash,
+    /// Percent-encoding: every escaped byte is written as `%XX`, including `%` itself as `%25`.
+    ///
+    /// The `escape_char` must be `%` and `x_digits` must be 2. There are no shorthands and no
+    /// `\u` escapes, so `Encoder::encode_u16` and line wrapping are not supported.
+    Percent,
+}
+
+/// Settings for encoding binary data as STFU-8.
+///
+/// The `encode_*` functions use [`Encoder::new`](#method.new) or
+/// [`Encoder::pretty`](#method.pretty). Start from one of those and change the settings to
+/// customize the encoding.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let mut encoder = stfu8::Encoder::pretty();
+/// encoder.escape_del = false;
+/// encoder.literal_controls = b"\x1B";
+/// assert_eq!(
+///     encoder.encode_u8(b"\x1B[31mred\x7F\x00\n"),
+///     "\x1B[31mred\x7F\\x00\n"
+/// );
+/// # }
+/// ```
+///
+/// A modified copy can also be built with the `with_*` methods:
+///
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let base = stfu8::Encoder::pretty();
+/// let encoder = base.with_encode_tab(true);
+/// assert_eq!(encoder.encode_u8(b"\tfoo\n"), "\\tfoo\n");
+/// assert_ne!(encoder, base);
+/// assert_eq!(stfu8::Encoder::default(), stfu8::Encoder::new());
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Encoder {
+    /// Escape tab as `\t`.
+    pub encode_tab: bool, // \t \x09
+    /// Escape line feed as `\n`.
+    pub encode_line_feed: bool, // \n \x0A
+    /// Escape cariage return as `\r`.
+    pub encode_cariage: bool, // \r \x0D
+    /// Escape the null byte as `\0` instead of `\x00`.
+    pub encode_null: bool, // \0 \x00
+    /// Escape form feed as `\x0C`. If `false` it is written literally.
+    pub encode_form_feed: bool, // \x0C
+    /// Escape vertical tab as `\x0B`. If `false` it is written literally.
+    pub encode_vtab: bool, // \x0B
+    /// Escape tab, line feed and cariage return as `\x09`, `\x0A` and `\x0D` instead of `\t`,
+    /// `\n` and `\r`, for parsers which only understand `\x` escapes.
+    ///
+    /// Only affects the characters which are escaped at all, see `encode_tab` etc.
+    pub hex_only_controls: bool,
+    /// Escape chars outside the basic multilingual plane (`U+10000` and above, i.e. most emoji)
+    /// with the brace form of a `\u` escape, i.e. `\u{1F600}`, so their code point is visible.
+    pub escape_supplementary: bool,
+    /// Escape chars outside the basic multilingual plane with a `\U` escape of exactly eight hex
+    /// digits, i.e. `\U0001F600`, as written by Python. Takes precedence over
+    /// `escape_supplementary`.
+    pub escape_supplementary_long: bool,
+    /// Write `\u` escapes of `u16` values with four hex digits, i.e. `\uD800` instead of
+    /// `\u00D800`.
+    ///
+    /// A char outside the basic multilingual plane doesn't fit, so with `ascii_only` it is
+    /// written as `\U0001F600` instead. The decoder must set
+    /// [`short_u_escapes`](struct.Decoder.html#structfield.short_u_escapes).
+    pub short_u_escapes: bool,
+    /// Escape the DEL character (`\x7F`) when encoding `u8`. If `false` it is written literally.
+    pub escape_del: bool,
+    /// Escape everything which is not ASCII, even if it is valid, so that the output is pure
+    /// ASCII.
+    ///
+    /// When encoding `u8` every such byte is escaped with `\x`, i.e. `é` is encoded as
+    /// `\xC3\xA9`. When encoding `u16` every such unit is escaped with `\u`, i.e. `\u0000E9`,
+    /// except that a valid surrogate pair is escaped as the char it encodes (i.e. `\u01F600`, or
+    /// with `escape_supplementary`).
+    ///
+    /// Takes precedence over `escape_supplementary` when encoding `u8`.
+    pub ascii_only: bool,
+    /// Escape the spaces at the start and end of the input as `\x20`, since they are easily lost
+    /// by systems which trim values. Spaces between other characters are still written as-is.
+    pub escape_edge_spaces: bool,
+    /// Escape the line and paragraph separators (`U+2028` and `U+2029`) with `\u`, i.e.
+    /// `\u002028`, even in pretty mode. They are valid text, but break many displays and
+    /// JavaScript strings.
+    pub escape_line_separators: bool,
+    /// ASCII control characters (`\x00` to `\x1F`) which are written literally instead of being
+    /// escaped.
+    ///
+    /// Only ASCII controls can be written literally: any other byte which is not part of valid
+    /// UTF-8 must be escaped. The C1 control *characters* (`U+0080` to `U+009F`) are valid UTF-8
+    /// and are never escaped.
+    pub literal_controls: &'static [u8],
+    /// The character which starts an escape, `\` by default. It is escaped by doubling it.
+    ///
+    /// Must be ASCII punctuation. The decoder must use the same character.
+    pub escape_char: char,
+    /// The family of escapes to write, see [`EscapeStyle`](enum.EscapeStyle.html). The decoder
+    /// must use the same style.
+    pub escape_style: EscapeStyle,
+    /// Number of hex digits in a `\x` escape when encoding `u8`. Must be even and non-zero.
+    ///
+    /// Each escape holds `x_digits / 2` bytes, written big-endian. Only the final escape of the
+    /// output may be short. The decoder must use the same value.
+    pub x_digits: usize,
+    /// Wrap the output so that lines are shorter than this many chars, by ending them with a line
+    /// continuation: the escape character followed by a line feed.
+    ///
+    /// Escapes are never split across lines. The decoder must set
+    /// [`allow_line_continuation`](struct.Decoder.html#structfield.allow_line_continuation).
+    pub wrap_at: Option<usize>,
+}
+
+impl Encoder {
+    /// Create a new "non pretty" `Encoder`.
+    ///
+    /// ALL non-printable characters will be escaped
     pub fn new() -> Encoder {
         Encoder {
             encode_tab: true,
             encode_line_feed: true,
             encode_cariage: true,
+            encode_null: false,
+            encode_form_feed: true,
+            encode_vtab: true,
+            hex_only_controls: false,
+            escape_supplementary: false,
+            escape_supplementary_long: false,
+            short_u_escapes: false,
+            escape_del: true,
+            ascii_only: false,
+            escape_edge_spaces: false,
+            escape_line_separators: false,
+            literal_controls: &[],
+            escape_char: helpers::BSLASH,
+            escape_style: EscapeStyle::Backslash,
+            x_digits: 2,
+            wrap_at: None,
         }
     }
 
@@ -332,6 +1299,745 @@ impl Encoder {
             encode_tab: false,
             encode_line_feed: false,
             encode_cariage: false,
+            ..Encoder::new()
+        }
+    }
+
+    /// Create an `Encoder` for the escapes of Python string literals.
+    ///
+    /// It writes `\t`, `\n`, `\r`, `\\`, `\xNN` for the other control characters and invalid
+    /// UTF-8, `\uXXXX` for (unpaired surrogate) `u16` values and `\UXXXXXXXX` for chars outside
+    /// the basic multilingual plane. Everything else, including quotes, is written as-is.
+    ///
+    /// Note that Python reads `\xNN` in a `str` (unlike `bytes`) as the char `U+00NN`. Decode the
+    /// output with [`Decoder::python`](struct.Decoder.html#method.python).
+    pub fn python() -> Encoder {
+        Encoder {
+            short_u_escapes: true,
+            escape_supplementary_long: true,
+            ..Encoder::new()
+        }
+    }
+
+    /// Create an `Encoder` for the escapes of Rust string literals.
+    ///
+    /// It writes `\t`, `\n`, `\r`, `\\`, `\xNN` for the other control characters and invalid
+    /// UTF-8 and `\u{...}` for chars outside the basic multilingual plane. Everything else,
+    /// including quotes, is written as-is.
+    ///
+    /// Note that Rust only allows `\x80` and above in byte strings, and has no escape for
+    /// (unpaired surrogate) `u16` values, which are written as `\u00D800`. Decode the output
+    /// with [`Decoder::rust`](struct.Decoder.html#method.rust).
+    pub fn rust() -> Encoder {
+        Encoder {
+            escape_supplementary: true,
+            ..Encoder::new()
+        }
+    }
+
+    /// Create an `Encoder` for the escapes of `printf`, `echo -e` and bash's `$'...'`.
+    ///
+    /// It writes `\t`, `\n`, `\r`, `\\`, `\xNN` for the other control characters and invalid
+    /// UTF-8 and `\uXXXX` for (unpaired surrogate) `u16` values. Everything else, including
+    /// quotes, is written as-is. Decode the output with
+    /// [`Decoder::shell`](struct.Decoder.html#method.shell).
+    pub fn shell() -> Encoder {
+        Encoder {
+            short_u_escapes: true,
+            ..Encoder::new()
+        }
+    }
+
+    /// Return the `Encoder` with [`encode_tab`](#structfield.encode_tab) set to `value`.
+    pub fn with_encode_tab(mut self, value: bool) -> Encoder {
+        self.encode_tab = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`encode_line_feed`](#structfield.encode_line_feed) set to `value`.
+    pub fn with_encode_line_feed(mut self, value: bool) -> Encoder {
+        self.encode_line_feed = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`encode_cariage`](#structfield.encode_cariage) set to `value`.
+    pub fn with_encode_cariage(mut self, value: bool) -> Encoder {
+        self.encode_cariage = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`encode_null`](#structfield.encode_null) set to `value`.
+    pub fn with_encode_null(mut self, value: bool) -> Encoder {
+        self.encode_null = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`encode_form_feed`](#structfield.encode_form_feed) set to `value`.
+    pub fn with_encode_form_feed(mut self, value: bool) -> Encoder {
+        self.encode_form_feed = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`encode_vtab`](#structfield.encode_vtab) set to `value`.
+    pub fn with_encode_vtab(mut self, value: bool) -> Encoder {
+        self.encode_vtab = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`hex_only_controls`](#structfield.hex_only_controls) set to `value`.
+    pub fn with_hex_only_controls(mut self, value: bool) -> Encoder {
+        self.hex_only_controls = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`escape_supplementary`](#structfield.escape_supplementary) set to `value`.
+    pub fn with_escape_supplementary(mut self, value: bool) -> Encoder {
+        self.escape_supplementary = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`escape_supplementary_long`](#structfield.escape_supplementary_long)
+    /// set to `value`.
+    pub fn with_escape_supplementary_long(mut self, value: bool) -> Encoder {
+        self.escape_supplementary_long = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`short_u_escapes`](#structfield.short_u_escapes) set to `value`.
+    pub fn with_short_u_escapes(mut self, value: bool) -> Encoder {
+        self.short_u_escapes = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`escape_del`](#structfield.escape_del) set to `value`.
+    pub fn with_escape_del(mut self, value: bool) -> Encoder {
+        self.escape_del = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`ascii_only`](#structfield.ascii_only) set to `value`.
+    pub fn with_ascii_only(mut self, value: bool) -> Encoder {
+        self.ascii_only = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`escape_edge_spaces`](#structfield.escape_edge_spaces) set to
+    /// `value`.
+    pub fn with_escape_edge_spaces(mut self, value: bool) -> Encoder {
+        self.escape_edge_spaces = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`escape_line_separators`](#structfield.escape_line_separators)
+    /// set to `value`.
+    pub fn with_escape_line_separators(mut self, value: bool) -> Encoder {
+        self.escape_line_separators = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`literal_controls`](#structfield.literal_controls) set to `value`.
+    pub fn with_literal_controls(mut self, value: &'static [u8]) -> Encoder {
+        self.literal_controls = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`escape_char`](#structfield.escape_char) set to `value`.
+    pub fn with_escape_char(mut self, value: char) -> Encoder {
+        self.escape_char = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`escape_style`](#structfield.escape_style) set to `value`.
+    ///
+    /// This also sets [`escape_char`](#structfield.escape_char) to the character which starts
+    /// the escapes of the style, i.e. `%` for `EscapeStyle::Percent`.
+    pub fn with_escape_style(mut self, value: EscapeStyle) -> Encoder {
+        self.escape_style = value;
+        self.escape_char = match value {
+            EscapeStyle::Backslash => helpers::BSLASH,
+            EscapeStyle::Percent => '%',
+        };
+        self
+    }
+
+    /// Return the `Encoder` with [`x_digits`](#structfield.x_digits) set to `value`.
+    pub fn with_x_digits(mut self, value: usize) -> Encoder {
+        self.x_digits = value;
+        self
+    }
+
+    /// Return the `Encoder` with [`wrap_at`](#structfield.wrap_at) set to `value`.
+    pub fn with_wrap_at(mut self, value: Option<usize>) -> Encoder {
+        self.wrap_at = value;
+        self
+    }
+
+    /// Create a header describing these settings, to be prepended to the encoded data so that
+    /// [`decode_auto`](fn.decode_auto.html) can decode it.
+    ///
+    /// The header is a single line such as `#stfu8:1,e%,x4` for version 1 of the format with `%`
+    /// as the escape character and 4 digit `\x` escapes.
+    pub fn emit_profile_tag(&self) -> String {
+        self.check();
+        profile::profile_tag(self)
+    }
+
+    /// Encode text as STFU-8 using these settings.
+    ///
+    /// See [`encode_u8`](fn.encode_u8.html).
+    pub fn encode_u8(&self, v: &[u8]) -> String {
+        self.check();
+        self.wrap(encode_u8::encode(self, v))
+    }
+
+    /// Create a [`StreamEncoder`](struct.StreamEncoder.html) to encode bytes which arrive in
+    /// chunks using these settings.
+    ///
+    /// [`wrap_at`](#structfield.wrap_at) must not be set, since the lines can't be wrapped one
+    /// chunk at a time.
+    pub fn encode_u8_stream(&self) -> StreamEncoder {
+        self.check();
+        assert!(
+            self.wrap_at.is_none(),
+            "encode_u8_stream doesn't support wrap_at"
+        );
+        StreamEncoder::new(*self)
+    }
+
+    /// Classify how the byte `b` is written using these settings.
+    ///
+    /// See [`classify_byte`](fn.classify_byte.html).
+    pub fn classify_byte(&self, b: u8) -> ByteClass {
+        helpers::classify_byte(self, b)
+    }
+
+    /// Encode a `str` as STFU-8 using these settings.
+    ///
+    /// See [`encode_str`](fn.encode_str.html).
+    pub fn encode_str(&self, s: &str) -> String {
+        self.check();
+        self.wrap(encode_u8::encode_str(self, s))
+    }
+
+    /// Encode a single `char` as STFU-8 using these settings.
+    ///
+    /// See [`encode_char`](fn.encode_char.html).
+    pub fn encode_char(&self, c: char) -> String {
+        self.encode_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Encode the chars as STFU-8 using these settings.
+    ///
+    /// See [`encode_chars`](fn.encode_chars.html).
+    pub fn encode_chars<I: IntoIterator<Item = char>>(&self, chars: I) -> String {
+        self.check();
+        self.wrap(encode_u8::encode_chars(self, chars.into_iter()))
+    }
+
+    /// Encode text as STFU-8 using these settings, escaping the bytes marked in `mask`.
+    ///
+    /// See [`encode_u8_masked`](fn.encode_u8_masked.html).
+    pub fn encode_u8_masked(&self, v: &[u8], mask: &EscapeMask) -> String {
+        self.check();
+        self.wrap(encode_u8::encode_masked(self, v, mask))
+    }
+
+    /// Encode text as STFU-8 using these settings, also escaping every byte for which
+    /// `should_escape` returns `true`.
+    ///
+    /// See [`encode_u8_with`](fn.encode_u8_with.html).
+    pub fn encode_u8_with<F: Fn(u8) -> bool>(&self, v: &[u8], should_escape: F) -> String {
+        let mask: EscapeMask = v.iter().map(|b| should_escape(*b)).collect();
+        self.encode_u8_masked(v, &mask)
+    }
+
+    /// The exact length in bytes of [`encode_u8`](#method.encode_u8)`(v)` using these settings.
+    ///
+    /// See [`encoded_len_u8`](fn.encoded_len_u8.html).
+    pub fn encoded_len_u8(&self, v: &[u8]) -> usize {
+        self.check();
+        match self.wrap_at {
+            Some(_) => self.encode_u8(v).len(),
+            None => encode_u8::encoded_len(self, v),
+        }
+    }
+
+    /// Whether [`encode_u8`](#method.encode_u8)`(v)` using these settings would differ from `v`.
+    ///
+    /// See [`needs_encoding`](fn.needs_encoding.html).
+    pub fn needs_encoding(&self, v: &[u8]) -> bool {
+        self.check();
+        encode_u8::needs_encoding(self, v)
+            || self.wrap_at.is_some() && self.encode_u8(v).as_bytes() != v
+    }
+
+    /// Encode UTF-16 as STFU-8 using these settings.
+    ///
+    /// See [`encode_u16`](fn.encode_u16.html).
+    pub fn encode_u16(&self, v: &[u16]) -> String {
+        self.check();
+        assert!(
+            self.escape_style == EscapeStyle::Backslash,
+            "encode_u16 requires EscapeStyle::Backslash"
+        );
+        self.wrap(encode_u16::encode(self, v))
+    }
+
+    /// Wrap the encoded output if [`wrap_at`](#structfield.wrap_at) is set.
+    fn wrap(&self, encoded: String) -> String {
+        match self.wrap_at {
+            Some(width) => wrap::wrap(self, &encoded, width),
+            None => encoded,
+        }
+    }
+
+    fn check(&self) {
+        assert!(
+            self.x_digits > 0 && self.x_digits.is_multiple_of(2),
+            "x_digits must be even and non-zero: {}",
+            self.x_digits
+        );
+        assert!(
+            self.escape_char.is_ascii_punctuation(),
+            "escape_char must be ASCII punctuation: {:?}",
+            self.escape_char
+        );
+        if self.escape_style == EscapeStyle::Percent {
+            assert!(
+                self.escape_char == '%' && self.x_digits == 2 && self.wrap_at.is_none(),
+                "EscapeStyle::Percent requires escape_char '%', x_digits 2 and no wrap_at"
+            );
         }
     }
 }
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
+
+/// Settings for decoding STFU-8.
+///
+/// The `decode_*` functions use [`Decoder::new`](#method.new). Start from that and change the
+/// settings to customize the decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Decoder {
+    /// Number of hex digits in a `\x` escape. Must be even and non-zero.
+    ///
+    /// The digits are a big-endian value which is split into `x_digits / 2` bytes. The final
+    /// escape of the input may have fewer (but still an even number of) digits.
+    pub x_digits: usize,
+    /// Reject any `\u` escape which is not a unicode scalar value (i.e. surrogates or values
+    /// above `\u10FFFF`) with [`NotAScalar`](enum.DecodeErrorKind.html#variant.NotAScalar),
+    /// no matter what they are decoded into.
+    ///
+    /// By default such values are decoded as-is if they fit (i.e. surrogates in `decode_u16`).
+    pub reject_non_scalar: bool,
+    /// The character which starts an escape, `\` by default. It is escaped by doubling it.
+    ///
+    /// Must be ASCII punctuation.
+    pub escape_char: char,
+    /// The family of escapes to read, see [`EscapeStyle`](enum.EscapeStyle.html).
+    ///
+    /// With `EscapeStyle::Percent` the [`escape_char`](#structfield.escape_char) must be `%` and
+    /// only `%XX` escapes are decoded.
+    pub escape_style: EscapeStyle,
+    /// Escapes to decode besides the STFU-8 ones, i.e. the octal escapes of `printf` with
+    /// [`EscapeSpec::with_octal`](struct.EscapeSpec.html#method.with_octal).
+    pub escape_spec: EscapeSpec,
+    /// Also decode the uppercase shorthands `\T`, `\N` and `\R` as tab, line feed and cariage
+    /// return. This does not affect `\x` and `\u`.
+    pub case_insensitive_shorthands: bool,
+    /// Decode `\u` escapes with exactly four hex digits, i.e. `\uD800`, as written by
+    /// [`Encoder::short_u_escapes`](struct.Encoder.html#structfield.short_u_escapes).
+    ///
+    /// Larger values must use the brace form `\u{1F600}` or `\U0001F600`, so every escape has a
+    /// single width and `\u00411` is always `A` followed by `1`.
+    pub short_u_escapes: bool,
+    /// Combine a `\u` escaped UTF-16 lead surrogate immediately followed by a `\u` escaped trail
+    /// surrogate into the char they encode, i.e. `\u00D83D\u00DE00` into `😀`.
+    ///
+    /// Surrogates which are not part of such a pair are decoded as usual.
+    pub recombine_surrogates: bool,
+    /// Decode a `\u` escape of a UTF-16 surrogate as the replacement character `U+FFFD`, so
+    /// that `decode_u16` returns well-formed UTF-16.
+    ///
+    /// Only surrogates combined by [`recombine_surrogates`](#structfield.recombine_surrogates)
+    /// are kept. This takes precedence over
+    /// [`reject_non_scalar`](#structfield.reject_non_scalar) for surrogates.
+    pub replace_lone_surrogates: bool,
+    /// Decode every `\u` escape as a single value, like a `\x` escape.
+    ///
+    /// By default a `\u` escape of a unicode scalar value is decoded as that char, i.e. the
+    /// multiple UTF-8 bytes of `\u000156` (`Ŗ`) in `decode_u8`. With this set `decode_u8` stays
+    /// byte-oriented: `\u0000FF` is the byte `0xFF` and anything larger is an
+    /// [`InvalidValue`](enum.DecodeErrorKind.html#variant.InvalidValue) error. Likewise
+    /// `decode_u16` rejects escapes of supplementary-plane chars.
+    pub strict_u8: bool,
+    /// Skip a byte order mark (`U+FEFF`, the bytes `EF BB BF`) at the start of the input, as
+    /// written by some editors.
+    ///
+    /// By default it is decoded like any other char. Error indexes are still into the input
+    /// including the BOM.
+    pub strip_bom: bool,
+    /// Decode a line continuation (the escape character followed by a line feed, or a cariage
+    /// return and line feed) as nothing, as written by
+    /// [`Encoder::wrap_at`](struct.Encoder.html#structfield.wrap_at) or by hand.
+    ///
+    /// By default it is an [`UnescapedSlash`](enum.DecodeErrorKind.html#variant.UnescapedSlash)
+    /// error.
+    pub allow_line_continuation: bool,
+    /// Decode each literal cariage return and line feed (`\r\n`) of the input as a single line
+    /// feed, for text edited on Windows.
+    ///
+    /// Escapes are never affected, so `\r\n` written as escapes is still decoded as both bytes.
+    pub normalize_crlf: bool,
+    /// Reject a `\x` or `\u` escape which is directly followed by another hex digit, i.e.
+    /// `\x411`, with [`OverlongEscape`](enum.DecodeErrorKind.html#variant.OverlongEscape).
+    ///
+    /// This catches escapes copied from formats with wider escapes. Note that the `Encoder` can
+    /// create such text: the bytes `\xFF` and `A` are encoded as `\xFFA`.
+    pub reject_overlong_escapes: bool,
+    /// Reject control characters (below `U+0020`) which are written literally instead of being
+    /// escaped, with [`LiteralControl`](enum.DecodeErrorKind.html#variant.LiteralControl).
+    ///
+    /// Tab, line feed and cariage return are still allowed, since a "pretty" `Encoder` writes
+    /// them literally. This keeps text which is meant to be printed from injecting e.g. terminal
+    /// escape sequences.
+    pub reject_literal_controls: bool,
+    /// Reject the escape character of the other common dialect when it is not escaped, with
+    /// [`MixedEscapeChar`](enum.DecodeErrorKind.html#variant.MixedEscapeChar): `\` if the
+    /// [`escape_char`](#structfield.escape_char) is not `\`, otherwise `%`.
+    ///
+    /// This catches text written for a different decoder, i.e. `\x1B` decoded with `%` escapes.
+    /// Note that the `Encoder` writes the other character as-is, so only use this for input
+    /// which should not contain it.
+    pub strict_escape_char: bool,
+    /// Reject `\x` and `\u` escapes with a lowercase hex digit, i.e. `\xfF`, with
+    /// [`NonCanonicalHex`](enum.DecodeErrorKind.html#variant.NonCanonicalHex) at the index of the
+    /// first lowercase digit.
+    ///
+    /// The `Encoder` always writes uppercase hex, so this enforces its canonical form.
+    pub require_uppercase_hex: bool,
+    /// The maximum number of levels [`decode_u8_nested`](#method.decode_u8_nested) will decode.
+    ///
+    /// This bounds the work done on adversarial input no matter how many levels are requested.
+    pub max_nesting: usize,
+}
+
+impl Decoder {
+    /// Create a new `Decoder` with the default settings.
+    pub fn new() -> Decoder {
+        Decoder {
+            x_digits: 2,
+            reject_non_scalar: false,
+            escape_char: helpers::BSLASH,
+            escape_style: EscapeStyle::Backslash,
+            escape_spec: EscapeSpec::stfu8(),
+            case_insensitive_shorthands: false,
+            short_u_escapes: false,
+            recombine_surrogates: false,
+            replace_lone_surrogates: false,
+            strict_u8: false,
+            strip_bom: false,
+            allow_line_continuation: false,
+            normalize_crlf: false,
+            reject_overlong_escapes: false,
+            reject_literal_controls: false,
+            strict_escape_char: false,
+            require_uppercase_hex: false,
+            max_nesting: 8,
+        }
+    }
+
+    /// Create a `Decoder` for the contents of Python string literals (without the quotes).
+    ///
+    /// Besides the escapes written by [`Encoder::python`](struct.Encoder.html#method.python) it
+    /// reads the escapes of [`EscapeSpec::python`](struct.EscapeSpec.html#method.python) (octal,
+    /// `\a`, `\b`, `\f`, `\v`, `\'` and `\"`), `\N{NAME}` with the `unicode-names` feature and
+    /// line continuations. `\u` escapes have exactly four hex digits.
+    ///
+    /// Like in a Python `bytes` literal (but not a `str`), `\xNN` is decoded as the byte `0xNN`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # extern crate stfu8;
+    ///
+    /// # fn main() {
+    /// let decoder = stfu8::Decoder::python();
+    /// let literal = r#"it\'s \"\U0001F600\"\a\101\u00e9"#;
+    /// assert_eq!(decoder.decode_u8(literal).unwrap(), "it's \"😀\"\x07Aé".as_bytes());
+    /// assert_eq!(decoder.decode_u8(r"\xFF").unwrap(), b"\xFF");
+    /// # }
+    /// ```
+    pub fn python() -> Decoder {
+        Decoder {
+            escape_spec: EscapeSpec::python(),
+            short_u_escapes: true,
+            allow_line_continuation: true,
+            ..Decoder::new()
+        }
+    }
+
+    /// Create a `Decoder` for the contents of Rust string literals (without the quotes).
+    ///
+    /// Besides the escapes written by [`Encoder::rust`](struct.Encoder.html#method.rust) it reads
+    /// the escapes of [`EscapeSpec::rust`](struct.EscapeSpec.html#method.rust) (`\'` and `\"`)
+    /// and line continuations. Unlike Rust, the indentation after a line continuation is kept.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # extern crate stfu8;
+    ///
+    /// # fn main() {
+    /// let decoder = stfu8::Decoder::rust();
+    /// let literal = r#"it\'s \"\u{1F600}\"\0\x41\u{e9}"#;
+    /// assert_eq!(decoder.decode_u8(literal).unwrap(), "it's \"😀\"\0Aé".as_bytes());
+    /// # }
+    /// ```
+    pub fn rust() -> Decoder {
+        Decoder {
+            escape_spec: EscapeSpec::rust(),
+            allow_line_continuation: true,
+            ..Decoder::new()
+        }
+    }
+
+    /// Create a `Decoder` for the escapes of `printf`, `echo -e` and bash's `$'...'`.
+    ///
+    /// Besides the escapes written by [`Encoder::shell`](struct.Encoder.html#method.shell) it
+    /// reads the escapes of [`EscapeSpec::shell`](struct.EscapeSpec.html#method.shell) (octal,
+    /// `\a`, `\b`, `\e`, `\f`, `\v`, `\'` and `\"`) and `\UXXXXXXXX`. `\u` escapes have
+    /// exactly four hex digits and `\x` escapes two. Octal escapes have at most three digits, so
+    /// the four digit form `\0NNN` of `echo -e` is not supported.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # extern crate stfu8;
+    ///
+    /// # fn main() {
+    /// let decoder = stfu8::Decoder::shell();
+    /// let escaped = r"\e[31mred\033[0m\u00e9\U0001F600";
+    /// assert_eq!(decoder.decode_u8(escaped).unwrap(), "\x1B[31mred\x1B[0mé😀".as_bytes());
+    /// # }
+    /// ```
+    pub fn shell() -> Decoder {
+        Decoder {
+            escape_spec: EscapeSpec::shell(),
+            short_u_escapes: true,
+            ..Decoder::new()
+        }
+    }
+
+    /// Decode STFU-8 into binary using these settings.
+    ///
+    /// See [`decode_u8`](fn.decode_u8.html).
+    pub fn decode_u8(&self, s: &str) -> Result<Vec<u8>, DecodeError> {
+        self.check();
+        let mut out: Vec<u8> = Vec::with_capacity(s.len());
+        decode::decode_generic(self, |val| decode::push_u8(&mut out, val), s)?;
+        Ok(out)
+    }
+
+    /// Decode STFU-8 into a `Box<[u8]>` using these settings.
+    ///
+    /// See [`decode_u8_boxed`](fn.decode_u8_boxed.html).
+    pub fn decode_u8_boxed(&self, s: &str) -> Result<Box<[u8]>, DecodeError> {
+        self.check();
+        let len = decode::decoded_len_u8(self, s)?;
+        let mut out: Vec<u8> = Vec::with_capacity(len);
+        decode::decode_generic(self, |val| decode::push_u8(&mut out, val), s)?;
+        debug_assert_eq!(out.len(), len);
+        Ok(out.into_boxed_slice())
+    }
+
+    /// Decode STFU-8 into binary using these settings, continuing past errors.
+    ///
+    /// See [`decode_u8_collect_errors`](fn.decode_u8_collect_errors.html).
+    pub fn decode_u8_collect_errors(&self, s: &str) -> (Vec<u8>, Vec<DecodeError>) {
+        self.check();
+        let mut out: Vec<u8> = Vec::with_capacity(s.len());
+        let mut errors = Vec::new();
+        decode::decode_generic_recover(
+            self,
+            |val| decode::push_u8(&mut out, val),
+            |err| errors.push(err),
+            s,
+        );
+        (out, errors)
+    }
+
+    /// Decode the STFU-8 text in `buf` into binary in place using these settings.
+    ///
+    /// See [`decode_u8_in_place`](fn.decode_u8_in_place.html).
+    pub fn decode_u8_in_place(&self, buf: &mut Vec<u8>) -> Result<(), DecodeError> {
+        self.check();
+        decode::decode_u8_in_place(self, buf)
+    }
+
+    /// Decode the longest valid prefix of STFU-8 into binary using these settings, returning it
+    /// and the number of bytes of `s` it was decoded from.
+    ///
+    /// See [`decode_u8_prefix`](fn.decode_u8_prefix.html).
+    pub fn decode_u8_prefix(&self, s: &str) -> (Vec<u8>, usize) {
+        let mut end = s.len();
+        loop {
+            match self.decode_u8(&s[..end]) {
+                Ok(out) => return (out, end),
+                // The text before the escape which failed is valid by itself, unless it ends
+                // with the lead of a surrogate pair whose trail failed. The error is always
+                // before `end`, so this stops.
+                Err(err) => end = self.escape_start(s, &err),
+            }
+        }
+    }
+
+    /// The index of `s` where the escape of `err` starts.
+    fn escape_start(&self, s: &str, err: &DecodeError) -> usize {
+        match err.kind() {
+            // the error is at the lowercase digit
+            DecodeErrorKind::NonCanonicalHex => {
+                s[..err.index()].rfind(self.escape_char).unwrap_or(0)
+            }
+            _ => err.index(),
+        }
+    }
+
+    /// Decode STFU-8 into binary using these settings, also returning which of the bytes were
+    /// escaped.
+    ///
+    /// See [`decode_u8_masked`](fn.decode_u8_masked.html).
+    pub fn decode_u8_masked(&self, s: &str) -> Result<(Vec<u8>, EscapeMask), DecodeError> {
+        self.check();
+        mask::decode(self, s)
+    }
+
+    /// Decode STFU-8 into a custom sink using these settings.
+    ///
+    /// See [`decode_into`](fn.decode_into.html).
+    pub fn decode_into<F>(&self, s: &str, f: F) -> Result<(), DecodeError>
+    where
+        F: FnMut(DecodedItem) -> Result<(), DecodeError>,
+    {
+        self.check();
+        decode::decode_into(self, s, f)
+    }
+
+    /// Decode STFU-8 which was encoded up to `levels` times, i.e. `\\x1B` for a `\x1B` byte
+    /// encoded twice.
+    ///
+    /// Each level is decoded with [`decode_u8`](#method.decode_u8) and decoding stops early once
+    /// the output has no escapes left. Every level but the last must decode to UTF-8, otherwise it
+    /// is a [`NotUtf8`](enum.DecodeErrorKind.html#variant.NotUtf8) error. If the output still has
+    /// escapes after [`max_nesting`](#structfield.max_nesting) levels it is a
+    /// [`TooDeeplyNested`](enum.DecodeErrorKind.html#variant.TooDeeplyNested) error.
+    ///
+    /// The `index` of an error is into the text of the level being decoded.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # extern crate stfu8;
+    ///
+    /// # fn main() {
+    /// let decoder = stfu8::Decoder::new();
+    /// assert_eq!(decoder.decode_u8_nested(r"foo\\x1B", 2).unwrap(), b"foo\x1B");
+    /// assert_eq!(decoder.decode_u8_nested(r"foo\\x1B", 1).unwrap(), br"foo\x1B");
+    /// # }
+    /// ```
+    pub fn decode_u8_nested(&self, s: &str, levels: usize) -> Result<Vec<u8>, DecodeError> {
+        let mut text = s.to_string();
+        for level in 0..levels {
+            let first = match text.find(self.escape_char) {
+                Some(index) => index,
+                None => break,
+            };
+            if level >= self.max_nesting {
+                // point at the first escape which is left
+                let err = DecodeError::new(DecodeErrorKind::TooDeeplyNested, first, String::new());
+                return Err(err.match_escape(self, &text).locate(&text));
+            }
+            if level + 1 == levels {
+                return self.decode_u8(&text);
+            }
+            // the invalid bytes are located at their escape in `text`
+            text = decode_as_str(self, &text)?;
+        }
+        Ok(text.into_bytes())
+    }
+
+    /// Decode STFU-8 into a `Vec<u16>` using these settings.
+    ///
+    /// See [`decode_u16`](fn.decode_u16.html).
+    pub fn decode_u16(&self, s: &str) -> Result<Vec<u16>, DecodeError> {
+        self.check();
+        let mut out: Vec<u16> = Vec::with_capacity(s.len());
+        {
+            let f = |val: decode::PushGeneric| -> Result<(), DecodeError> {
+                match val {
+                    decode::PushGeneric::Value { val, start } => {
+                        if val > u16::MAX as u32 {
+                            Err(DecodeError::new(
+                                DecodeErrorKind::InvalidValue,
+                                start,
+                                String::new(),
+                            ))
+                        } else {
+                            out.push(val as u16);
+                            Ok(())
+                        }
+                    }
+                    decode::PushGeneric::String(s) => {
+                        for c in s.chars() {
+                            let mut buf = [0u16; 2];
+                            out.extend_from_slice(c.encode_utf16(&mut buf));
+                        }
+                        Ok(())
+                    }
+                    decode::PushGeneric::Char(c) => {
+                        out.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+                        Ok(())
+                    }
+                    decode::PushGeneric::Bytes { bytes, .. } => {
+                        out.extend(bytes.iter().map(|b| u16::from(*b)));
+                        Ok(())
+                    }
+                }
+            };
+            decode::decode_generic(self, f, s)?;
+        }
+        Ok(out)
+    }
+
+    fn check(&self) {
+        assert!(
+            self.x_digits > 0 && self.x_digits.is_multiple_of(2),
+            "x_digits must be even and non-zero: {}",
+            self.x_digits
+        );
+        assert!(
+            self.escape_char.is_ascii_punctuation(),
+            "escape_char must be ASCII punctuation: {:?}",
+            self.escape_char
+        );
+        assert!(
+            self.escape_style == EscapeStyle::Backslash || self.escape_char == '%',
+            "EscapeStyle::Percent requires escape_char '%': {:?}",
+            self.escape_char
+        );
+        for entry in self.escape_spec.entries {
+            assert!(
+                (2..=16).contains(&entry.radix) && (1..=8).contains(&entry.max_digits),
+                "invalid escape spec entry: {:?}",
+                entry
+            );
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
@@ -0,0 +1,71 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+use std::iter::FromIterator;
+
+use crate::decode::{self, PushGeneric};
+use crate::{DecodeError, Decoder};
+
+/// Which bytes decoded by [`decode_u8_masked`](fn.decode_u8_masked.html) were written as
+/// escapes.
+///
+/// Passing it to [`encode_u8_masked`](fn.encode_u8_masked.html) escapes those bytes again, so
+/// that decoding and re-encoding a file doesn't change it. The mask only records *that* a byte
+/// was escaped, so a char from a `\u` escape is escaped again as `\x` escapes of its UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct EscapeMask {
+    escaped: Vec<bool>,
+}
+
+impl EscapeMask {
+    /// Whether the byte at `index` of the decoded data was escaped.
+    ///
+    /// Indexes past the end of the decoded data were not escaped.
+    pub fn is_escaped(&self, index: usize) -> bool {
+        self.escaped.get(index).cloned().unwrap_or(false)
+    }
+
+    fn push(&mut self, escaped: bool, len: usize) {
+        let new_len = self.escaped.len() + len;
+        self.escaped.resize(new_len, escaped);
+    }
+}
+
+/// Create a mask from whether each byte is escaped.
+impl FromIterator<bool> for EscapeMask {
+    fn from_iter<I: IntoIterator<Item = bool>>(iter: I) -> EscapeMask {
+        EscapeMask {
+            escaped: iter.into_iter().collect(),
+        }
+    }
+}
+
+pub(crate) fn decode(decoder: &Decoder, s: &str) -> Result<(Vec<u8>, EscapeMask), DecodeError> {
+    let mut out: Vec<u8> = Vec::with_capacity(s.len());
+    let mut mask = EscapeMask::default();
+    {
+        let f = |val: PushGeneric| -> Result<(), DecodeError> {
+            // only the text which was not escaped is unmasked
+            let escaped = !matches!(val, PushGeneric::String(_));
+            let len = out.len();
+            decode::push_u8(&mut out, val)?;
+            mask.push(escaped, out.len() - len);
+            Ok(())
+        };
+        decode::decode_generic(decoder, f, s)?;
+    }
+    Ok((out, mask))
+}
+
+#[test]
+fn sanity_decode_masked() {
+    let (bytes, mask) = decode(&Decoder::new(), r"a\x41\u0000A1b\n").unwrap();
+    assert_eq!(bytes, "aA\u{A1}b\n".as_bytes());
+    let escaped: Vec<_> = (0..bytes.len() + 1).map(|i| mask.is_escaped(i)).collect();
+    assert_eq!(escaped, [false, true, true, true, false, true, false]);
+}
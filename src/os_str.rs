@@ -0,0 +1,88 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! Encoding of `OsStr`, which is arbitrary bytes on unix and (possibly ill-formed) UTF-16 on
+//! windows.
+
+use std::ffi::{OsStr, OsString};
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(windows)]
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
+use crate::DecodeError;
+
+/// Encode an `OsStr` (i.e. a `Path`) as STFU-8.
+///
+/// On unix this encodes the bytes with [`encode_u8`](fn.encode_u8.html), on windows it encodes
+/// the UTF-16 with [`encode_u16`](fn.encode_u16.html). Either way the path can be restored
+/// exactly with [`decode_os_string`](fn.decode_os_string.html) on the same platform.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+/// use std::path::Path;
+///
+/// # fn main() {
+/// let path = Path::new("/tmp/foo\tbar");
+/// let encoded = stfu8::encode_os_str(path.as_os_str());
+/// assert_eq!(encoded, r"/tmp/foo\tbar");
+/// assert_eq!(stfu8::decode_os_string(&encoded).unwrap(), path.as_os_str());
+/// # }
+/// ```
+pub fn encode_os_str(s: &OsStr) -> String {
+    #[cfg(unix)]
+    {
+        crate::encode_u8(s.as_bytes())
+    }
+    #[cfg(windows)]
+    {
+        let wide: Vec<u16> = s.encode_wide().collect();
+        crate::encode_u16(&wide)
+    }
+}
+
+/// Decode STFU-8 created by [`encode_os_str`](fn.encode_os_str.html) into an `OsString`.
+///
+/// On unix this is [`decode_u8`](fn.decode_u8.html), on windows it is
+/// [`decode_u16`](fn.decode_u16.html).
+pub fn decode_os_string(s: &str) -> Result<OsString, DecodeError> {
+    #[cfg(unix)]
+    {
+        crate::decode_u8(s).map(OsString::from_vec)
+    }
+    #[cfg(windows)]
+    {
+        crate::decode_u16(s).map(|wide| OsString::from_wide(&wide))
+    }
+}
+
+#[test]
+fn sanity_os_str_roundtrip() {
+    let path = OsStr::new("C:\\foo\\¡ bar\n\u{1F600}");
+    let encoded = encode_os_str(path);
+    assert_eq!(encoded, "C:\\\\foo\\\\¡ bar\\n\u{1F600}");
+    assert_eq!(decode_os_string(&encoded).unwrap(), path);
+}
+
+#[cfg(unix)]
+#[test]
+fn sanity_os_str_not_utf8() {
+    let path = OsStr::from_bytes(b"/tmp/foo\xFF\xC2bar");
+    let encoded = encode_os_str(path);
+    assert_eq!(encoded, r"/tmp/foo\xFF\xC2bar");
+    assert_eq!(decode_os_string(&encoded).unwrap(), path);
+}
+
+#[cfg(windows)]
+#[test]
+fn sanity_os_str_unpaired_surrogate() {
+    let path = OsString::from_wide(&[0x66, 0x6F, 0xD800, 0x6F, 0xDC00]);
+    let encoded = encode_os_str(&path);
+    assert_eq!(encoded, r"fo\u00D800o\u00DC00");
+    assert_eq!(decode_os_string(&encoded).unwrap(), path);
+}
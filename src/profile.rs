@@ -0,0 +1,220 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! A self-describing header recording the settings data was encoded with.
+//!
+//! The grammar of the header is:
+//!
+//! ```text
+//! header  = "#stfu8:" version *( "," option ) "\n"
+//! version = "1"
+//! option  = "e" CHAR       ; the escape character, if not `\`
+//!         / "x" 1*DIGIT    ; the number of `\x` digits, if not 2
+//!         / "s"            ; `\u` escapes have four hex digits, see `Encoder::short_u_escapes`
+//!         / "p"            ; tab, line feed and cariage return are not escaped
+//!         / "w"            ; lines are wrapped with line continuations
+//!         / "%"            ; escapes are percent-encoded, see `EscapeStyle::Percent`
+//! ```
+//!
+//! Only the settings which change how the data must be decoded are required. `p` is
+//! informational, since the decoder always accepts both forms.
+
+use std::fmt::Write;
+
+use crate::{DecodeError, DecodeErrorKind, Decoder, Encoder, EscapeStyle};
+
+const HEADER: &str = "#stfu8:";
+const VERSION: char = '1';
+
+/// Create the header for data encoded by the `encoder`.
+pub(crate) fn profile_tag(encoder: &Encoder) -> String {
+    let mut out = String::from(HEADER);
+    out.push(VERSION);
+    if encoder.escape_char != Encoder::new().escape_char {
+        write!(out, ",e{}", encoder.escape_char).unwrap();
+    }
+    if encoder.x_digits != Encoder::new().x_digits {
+        write!(out, ",x{}", encoder.x_digits).unwrap();
+    }
+    if encoder.short_u_escapes {
+        out.push_str(",s");
+    }
+    if !encoder.encode_tab && !encoder.encode_line_feed && !encoder.encode_cariage {
+        out.push_str(",p");
+    }
+    if encoder.wrap_at.is_some() {
+        out.push_str(",w");
+    }
+    if encoder.escape_style == EscapeStyle::Percent {
+        out.push_str(",%");
+    }
+    out.push('\n');
+    out
+}
+
+/// Decode STFU-8, configuring the decoder from the header created by
+/// [`Encoder::emit_profile_tag`](struct.Encoder.html#method.emit_profile_tag).
+///
+/// Data without a header is decoded with the default settings. A header with an unknown version
+/// or option is an [`InvalidTag`](enum.DecodeErrorKind.html#variant.InvalidTag) error. The
+/// `index` of all errors is into `s` (including the header).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let encoder = stfu8::Encoder::new().with_escape_char('%');
+/// let data = encoder.emit_profile_tag() + &encoder.encode_u8(b"C:\\foo\xFF");
+/// assert_eq!(data, "#stfu8:1,e%\nC:\\foo%xFF");
+/// assert_eq!(stfu8::decode_auto(&data).unwrap(), b"C:\\foo\xFF");
+///
+/// assert_eq!(stfu8::decode_auto(r"no\theader").unwrap(), b"no\theader");
+/// # }
+/// ```
+pub fn decode_auto(s: &str) -> Result<Vec<u8>, DecodeError> {
+    if !s.starts_with(HEADER) {
+        return crate::decode_u8(s);
+    }
+    let end = match s.find('\n') {
+        Some(end) => end,
+        None => return Err(invalid_tag(s, 0)),
+    };
+    let decoder = parse_header(s, end)?;
+    decoder
+        .decode_u8(&s[(end + 1)..])
+        .map_err(|err| err.offset(end + 1).locate(s))
+}
+
+/// Parse the header, which ends with the line feed at `end`.
+fn parse_header(s: &str, end: usize) -> Result<Decoder, DecodeError> {
+    let mut options = s[HEADER.len()..end].split(',');
+    let mut index = HEADER.len();
+    if options.next() != Some(VERSION.encode_utf8(&mut [0; 4])) {
+        return Err(invalid_tag(s, index));
+    }
+    index += VERSION.len_utf8();
+
+    let mut decoder = Decoder::new();
+    // the escape character may itself be a `,`
+    let mut rest = &s[index..end];
+    while let Some(option) = rest.strip_prefix(',') {
+        index += 1;
+        let mut chars = option.chars();
+        let len = match chars.next() {
+            Some('e') => match chars.next() {
+                Some(c) if c.is_ascii_punctuation() => {
+                    decoder.escape_char = c;
+                    2
+                }
+                _ => return Err(invalid_tag(s, index)),
+            },
+            Some('x') => {
+                let digits = option[1..].find(',').unwrap_or(option.len() - 1);
+                match option[1..(1 + digits)].parse::<usize>() {
+                    Ok(x) if x > 0 && x % 2 == 0 => {
+                        decoder.x_digits = x;
+                        1 + digits
+                    }
+                    _ => return Err(invalid_tag(s, index)),
+                }
+            }
+            Some('s') => {
+                decoder.short_u_escapes = true;
+                1
+            }
+            Some('p') => 1,
+            Some('w') => {
+                decoder.allow_line_continuation = true;
+                1
+            }
+            Some('%') => {
+                decoder.escape_style = EscapeStyle::Percent;
+                decoder.escape_char = '%';
+                1
+            }
+            _ => return Err(invalid_tag(s, index)),
+        };
+        index += len;
+        rest = &option[len..];
+    }
+    if !rest.is_empty() {
+        return Err(invalid_tag(s, index));
+    }
+    Ok(decoder)
+}
+
+fn invalid_tag(s: &str, index: usize) -> DecodeError {
+    DecodeError::new(DecodeErrorKind::InvalidTag, index, s[index..].to_string()).locate(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_auto;
+    use crate::{DecodeErrorKind, Encoder, EscapeStyle};
+
+    #[test]
+    fn sanity_profile_roundtrip() {
+        let v = b"C:\\foo\t%\n\xFF\xFE\x00,bar\r\n\xE2\x80\xA8";
+        let encoders = vec![
+            (Encoder::new(), "#stfu8:1\n"),
+            (Encoder::pretty(), "#stfu8:1,p\n"),
+            (Encoder::new().with_escape_char('%'), "#stfu8:1,e%\n"),
+            (Encoder::new().with_escape_char(','), "#stfu8:1,e,\n"),
+            (
+                Encoder::pretty().with_x_digits(4).with_escape_char(','),
+                "#stfu8:1,e,,x4,p\n",
+            ),
+            (Encoder::new().with_encode_null(true), "#stfu8:1\n"),
+            (Encoder::new().with_wrap_at(Some(4)), "#stfu8:1,w\n"),
+            (
+                Encoder::new()
+                    .with_escape_line_separators(true)
+                    .with_short_u_escapes(true),
+                "#stfu8:1,s\n",
+            ),
+            (
+                Encoder::pretty().with_escape_style(EscapeStyle::Percent),
+                "#stfu8:1,e%,p,%\n",
+            ),
+        ];
+        for (encoder, tag) in encoders {
+            assert_eq!(encoder.emit_profile_tag(), tag);
+            let data = encoder.emit_profile_tag() + &encoder.encode_u8(v);
+            assert_eq!(decode_auto(&data).unwrap(), v, "{}", data);
+        }
+    }
+
+    #[test]
+    fn sanity_profile_errors() {
+        for &(s, index) in &[
+            ("#stfu8:1", 0),
+            ("#stfu8:2\nfoo", 7),
+            ("#stfu8:1,q\nfoo", 9),
+            ("#stfu8:1,ea\nfoo", 9),
+            ("#stfu8:1,x3\nfoo", 9),
+            ("#stfu8:1,x\nfoo", 9),
+            ("#stfu8:1,p,\nfoo", 11),
+            ("#stfu8:1,pp\nfoo", 10),
+        ] {
+            let err = decode_auto(s).unwrap_err();
+            assert_eq!(
+                (&err.kind, err.index),
+                (&DecodeErrorKind::InvalidTag, index),
+                "{}",
+                s
+            );
+        }
+
+        let err = decode_auto("#stfu8:1,e%\nfoo\n%q").unwrap_err();
+        assert_eq!(
+            (&err.kind, err.index),
+            (&DecodeErrorKind::UnescapedSlash, 16)
+        );
+        assert_eq!((err.line(), err.column()), (3, 1));
+    }
+}
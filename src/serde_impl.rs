@@ -0,0 +1,99 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! `serde` support, enabled with the `serde` feature.
+//!
+//! [`Stfu8Bytes`](struct.Stfu8Bytes.html) serializes to (and deserializes from) a STFU-8
+//! encoded string.
+//!
+//! ```rust
+//! # extern crate stfu8;
+//! # extern crate serde_json;
+//!
+//! # fn main() {
+//! let bytes = stfu8::Stfu8Bytes(b"foo\xFF\nbar".to_vec());
+//! let json = serde_json::to_string(&bytes).unwrap();
+//! assert_eq!(json, r#""foo\\xFF\\nbar""#);
+//!
+//! let result: stfu8::Stfu8Bytes = serde_json::from_str(&json).unwrap();
+//! assert_eq!(bytes, result);
+//! # }
+//! ```
+
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::Stfu8Bytes;
+
+impl Serialize for Stfu8Bytes {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&super::encode_u8(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Stfu8Bytes {
+    fn deserialize<D>(deserializer: D) -> Result<Stfu8Bytes, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(Stfu8BytesVisitor)
+    }
+}
+
+struct Stfu8BytesVisitor;
+
+impl<'de> Visitor<'de> for Stfu8BytesVisitor {
+    type Value = Stfu8Bytes;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a STFU-8 encoded string")
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Stfu8Bytes, E>
+    where
+        E: de::Error,
+    {
+        match super::decode_u8(s) {
+            Ok(v) => Ok(Stfu8Bytes(v)),
+            Err(err) => Err(E::custom(format_args!(
+                "invalid STFU-8 {:?} at index {}",
+                err.kind(),
+                err.index()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use crate::Stfu8Bytes;
+
+    #[test]
+    fn sanity_serde_roundtrip() {
+        let bytes = Stfu8Bytes(b"foo\x00\xFE\tbar\\".to_vec());
+        let json = serde_json::to_string(&bytes).unwrap();
+        assert_eq!(json, r#""foo\\x00\\xFE\\tbar\\\\""#);
+
+        let result: Stfu8Bytes = serde_json::from_str(&json).unwrap();
+        assert_eq!(bytes, result);
+    }
+
+    #[test]
+    fn sanity_serde_error() {
+        let err = serde_json::from_str::<Stfu8Bytes>(r#""foo\\bar""#).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("UnescapedSlash"), "{}", msg);
+        assert!(msg.contains("index 3"), "{}", msg);
+    }
+}
@@ -0,0 +1,156 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+use std::fmt;
+use std::io;
+
+use crate::{encode_u8, Encoder};
+
+/// An output which STFU-8 is encoded into with [`write_escaped`](#method.write_escaped).
+///
+/// It is implemented for `String` and `Vec<u8>`, and for any `io::Write` wrapped in an
+/// [`IoSink`](struct.IoSink.html).
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// use stfu8::EscapeSink;
+///
+/// let encoder = stfu8::Encoder::new();
+/// let mut out = String::from("value: ");
+/// out.write_escaped(&encoder, b"foo\xFF\n").unwrap();
+/// assert_eq!(out, r"value: foo\xFF\n");
+///
+/// let mut file = stfu8::IoSink(Vec::new());
+/// file.write_escaped(&encoder, b"foo\xFF\n").unwrap();
+/// assert_eq!(file.0, br"foo\xFF\n");
+/// # }
+/// ```
+pub trait EscapeSink {
+    /// Push encoded text to the output.
+    fn push_str(&mut self, s: &str) -> io::Result<()>;
+
+    /// Push a single encoded char to the output.
+    fn push_char(&mut self, c: char) -> io::Result<()> {
+        self.push_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Encode `v` as STFU-8 using the `encoder`'s settings, pushing the output as it is encoded.
+    ///
+    /// The output is identical to [`Encoder::encode_u8`](struct.Encoder.html#method.encode_u8).
+    /// Only with [`wrap_at`](struct.Encoder.html#structfield.wrap_at) is it collected first,
+    /// since the lines are wrapped afterwards.
+    ///
+    /// An error of the output is returned as-is, and anything before it has already been pushed.
+    ///
+    /// Every push is a whole `&str`, so the bytes pushed into a `Vec<u8>` or an `IoSink` are
+    /// always valid UTF-8.
+    fn write_escaped(&mut self, encoder: &Encoder, v: &[u8]) -> io::Result<()> {
+        if encoder.wrap_at.is_some() {
+            return self.push_str(&encoder.encode_u8(v));
+        }
+        encoder.check();
+        let mut out = Adapter {
+            sink: self,
+            error: None,
+        };
+        match encode_u8::encode_fmt(encoder, v, &mut out) {
+            Ok(()) => Ok(()),
+            Err(fmt::Error) => Err(out
+                .error
+                .unwrap_or_else(|| io::Error::other("formatting failed"))),
+        }
+    }
+}
+
+impl EscapeSink for String {
+    fn push_str(&mut self, s: &str) -> io::Result<()> {
+        String::push_str(self, s);
+        Ok(())
+    }
+
+    fn push_char(&mut self, c: char) -> io::Result<()> {
+        self.push(c);
+        Ok(())
+    }
+}
+
+impl EscapeSink for Vec<u8> {
+    fn push_str(&mut self, s: &str) -> io::Result<()> {
+        self.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Push STFU-8 into any `io::Write`, such as a file or socket, see
+/// [`EscapeSink`](trait.EscapeSink.html).
+///
+/// The writes are not buffered, so wrap an unbuffered writer in an `io::BufWriter`.
+#[derive(Debug)]
+pub struct IoSink<W: io::Write>(pub W);
+
+impl<W: io::Write> EscapeSink for IoSink<W> {
+    fn push_str(&mut self, s: &str) -> io::Result<()> {
+        self.0.write_all(s.as_bytes())
+    }
+}
+
+/// Writes to an `EscapeSink` from the `fmt::Write` encode loop, keeping its error.
+struct Adapter<'a, S: EscapeSink + ?Sized> {
+    sink: &'a mut S,
+    error: Option<io::Error>,
+}
+
+impl<'a, S: EscapeSink + ?Sized> fmt::Write for Adapter<'a, S> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.sink.push_str(s).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        self.sink.push_char(c).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+#[test]
+fn sanity_write_escaped() {
+    let v = "foo\t¡\\\u{1F600}\x07".as_bytes();
+    let mut v = v.to_vec();
+    v.extend_from_slice(b"\xFF\xC2 ");
+    for encoder in &[
+        Encoder::new(),
+        Encoder::pretty().with_x_digits(4),
+        Encoder::new().with_wrap_at(Some(8)),
+        Encoder::new().with_escape_edge_spaces(true),
+    ] {
+        let expected = encoder.encode_u8(&v);
+        let mut out = String::new();
+        out.write_escaped(encoder, &v).unwrap();
+        assert_eq!(out, expected);
+        let mut out: Vec<u8> = Vec::new();
+        out.write_escaped(encoder, &v).unwrap();
+        assert_eq!(out, expected.as_bytes());
+        let mut out = IoSink(Vec::new());
+        out.write_escaped(encoder, &v).unwrap();
+        assert_eq!(out.0, expected.as_bytes());
+    }
+
+    // the error of the writer is kept
+    let mut buf = [0; 4];
+    let mut out = IoSink(&mut buf[..]);
+    let err = out.write_escaped(&Encoder::new(), b"foo\xFF").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    assert_eq!(&buf, b"foo\\");
+}
@@ -0,0 +1,166 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+/// The octal escapes of `printf` and `echo -e`, i.e. `\033` or `\101`.
+const OCTAL: &[SpecEntry] = &[
+    SpecEntry::octal(b'0'),
+    SpecEntry::octal(b'1'),
+    SpecEntry::octal(b'2'),
+    SpecEntry::octal(b'3'),
+    SpecEntry::octal(b'4'),
+    SpecEntry::octal(b'5'),
+    SpecEntry::octal(b'6'),
+    SpecEntry::octal(b'7'),
+];
+
+/// The single letter escapes of Python string literals besides `\t`, `\n` and `\r`.
+const PYTHON_SHORTHANDS: &[(u8, u8)] = &[
+    (b'a', 0x07),
+    (b'b', 0x08),
+    (b'f', 0x0C),
+    (b'v', 0x0B),
+    (b'\'', b'\''),
+    (b'"', b'"'),
+];
+
+/// The quote escapes of Rust string literals.
+const RUST_SHORTHANDS: &[(u8, u8)] = &[(b'\'', b'\''), (b'"', b'"')];
+
+/// The single letter escapes of `printf`, `echo -e` and bash's `$'...'` besides `\t`, `\n` and
+/// `\r`.
+const SHELL_SHORTHANDS: &[(u8, u8)] = &[
+    (b'a', 0x07),
+    (b'b', 0x08),
+    (b'e', 0x1B),
+    (b'f', 0x0C),
+    (b'v', 0x0B),
+    (b'\'', b'\''),
+    (b'"', b'"'),
+];
+
+/// A single escape of an [`EscapeSpec`](struct.EscapeSpec.html): the escape character and
+/// `prefix`, followed by digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpecEntry {
+    /// The byte after the escape character which starts the escape.
+    ///
+    /// If it is itself a digit in the `radix` it is also the first digit of the value, i.e. the
+    /// `1` of `\101`.
+    pub prefix: u8,
+    /// The base of the digits. Must be between 2 and 16.
+    pub radix: u32,
+    /// The most digits of the escape. At least one is required. Must be between 1 and 8.
+    pub max_digits: usize,
+}
+
+impl SpecEntry {
+    /// An octal escape of up to three digits, the first of which is `prefix`.
+    const fn octal(prefix: u8) -> SpecEntry {
+        SpecEntry {
+            prefix,
+            radix: 8,
+            max_digits: 3,
+        }
+    }
+}
+
+/// The escapes a [`Decoder`](struct.Decoder.html) reads besides the ones built into STFU-8,
+/// keyed by the byte after the escape character.
+///
+/// An escape of the spec takes precedence over a built-in escape with the same byte. Each
+/// decodes to a single value like a `\x` escape, which must fit in the decoded type.
+///
+/// # Examples
+/// ```rust
+/// # extern crate stfu8;
+///
+/// # fn main() {
+/// let mut decoder = stfu8::Decoder::new();
+/// decoder.escape_spec = stfu8::EscapeSpec::with_octal();
+/// assert_eq!(
+///     decoder.decode_u8(r"\033[31mred\033[0m \101\0").unwrap(),
+///     b"\x1B[31mred\x1B[0m A\x00"
+/// );
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EscapeSpec {
+    /// The additional escapes. Only the first entry for each prefix is used.
+    pub entries: &'static [SpecEntry],
+    /// Additional escapes of a single byte after the escape character, as `(byte, value)`, i.e.
+    /// `(b'a', 0x07)` decodes `\a` as the bell character. Only the first one for each byte is
+    /// used, and `entries` take precedence.
+    pub shorthands: &'static [(u8, u8)],
+}
+
+impl EscapeSpec {
+    /// No additional escapes, i.e. only STFU-8 is decoded.
+    pub const fn stfu8() -> EscapeSpec {
+        EscapeSpec {
+            entries: &[],
+            shorthands: &[],
+        }
+    }
+
+    /// Also decode the octal escapes of `printf` and `echo -e`: one to three octal digits, i.e.
+    /// `\033` for `ESC` or `\101` for `A`.
+    ///
+    /// `\0` on its own is still the null byte.
+    pub const fn with_octal() -> EscapeSpec {
+        EscapeSpec {
+            entries: OCTAL,
+            shorthands: &[],
+        }
+    }
+
+    /// The escapes of Python string literals which STFU-8 lacks: the octal escapes (like
+    /// [`with_octal`](#method.with_octal)), `\a`, `\b`, `\f`, `\v`, `\'` and `\"`.
+    pub const fn python() -> EscapeSpec {
+        EscapeSpec {
+            entries: OCTAL,
+            shorthands: PYTHON_SHORTHANDS,
+        }
+    }
+
+    /// The escapes of Rust string literals which STFU-8 lacks: `\'` and `\"`.
+    pub const fn rust() -> EscapeSpec {
+        EscapeSpec {
+            entries: &[],
+            shorthands: RUST_SHORTHANDS,
+        }
+    }
+
+    /// The escapes of `printf`, `echo -e` and bash's `$'...'` which STFU-8 lacks: the octal
+    /// escapes (like [`with_octal`](#method.with_octal)), `\a`, `\b`, `\e`, `\f`, `\v`, `\'` and
+    /// `\"`.
+    pub const fn shell() -> EscapeSpec {
+        EscapeSpec {
+            entries: OCTAL,
+            shorthands: SHELL_SHORTHANDS,
+        }
+    }
+
+    /// The entry for the escape started by `prefix`, if any.
+    pub(crate) fn find(&self, prefix: u8) -> Option<&SpecEntry> {
+        self.entries.iter().find(|entry| entry.prefix == prefix)
+    }
+
+    /// The value of the shorthand escape `b`, if any.
+    pub(crate) fn find_shorthand(&self, b: u8) -> Option<u8> {
+        self.shorthands
+            .iter()
+            .find(|(letter, _)| *letter == b)
+            .map(|(_, value)| *value)
+    }
+}
+
+impl Default for EscapeSpec {
+    fn default() -> EscapeSpec {
+        EscapeSpec::stfu8()
+    }
+}
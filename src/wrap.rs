@@ -0,0 +1,121 @@
+/* Copyright (c) 2018 Garrett Berg, vitiral@gmail.com
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+//! Wrapping of encoded STFU-8 with line continuations, see `Encoder::wrap_at`.
+
+use crate::Encoder;
+
+/// Insert a line continuation (the escape character followed by a line feed) wherever a line of
+/// the encoded `s` would otherwise be `width` or more chars long.
+///
+/// Escapes are never split, so a line with a single long escape may still be longer.
+pub(crate) fn wrap(encoder: &Encoder, s: &str, width: usize) -> String {
+    let mut out = String::with_capacity(s.len() + 2 * s.len() / width.max(1));
+    // chars written to the current line
+    let mut col = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let token = &rest[..token_len(encoder, rest)];
+        rest = &rest[token.len()..];
+        if token == "\n" {
+            out.push('\n');
+            col = 0;
+            continue;
+        }
+        let len = token.chars().count();
+        // leave room for the continuation at the end of the line
+        if col > 0 && col + len >= width {
+            out.push(encoder.escape_char);
+            out.push('\n');
+            col = 0;
+        }
+        out.push_str(token);
+        col += len;
+    }
+    out
+}
+
+/// The length in bytes of the escape or char at the start of the encoded `s`.
+fn token_len(encoder: &Encoder, s: &str) -> usize {
+    let bytes = s.as_bytes();
+    if !s.starts_with(encoder.escape_char) {
+        return s.chars().next().map_or(0, char::len_utf8);
+    }
+    match bytes.get(1) {
+        // the final escape may be short
+        Some(b'x') => {
+            2 + bytes[2..]
+                .iter()
+                .take(encoder.x_digits)
+                .take_while(|b| b.is_ascii_hexdigit())
+                .count()
+        }
+        Some(b'u') if bytes.get(2) == Some(&b'{') => {
+            3 + bytes[3..]
+                .iter()
+                .position(|b| *b == b'}')
+                .map_or(0, |end| end + 1)
+        }
+        Some(b'u') if encoder.short_u_escapes => 6,
+        Some(b'u') => 8,
+        Some(b'U') => 10,
+        // the shorthands and the escape character itself
+        Some(_) => 2,
+        None => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Decoder, Encoder};
+
+    fn assert_wrapped(encoder: &Encoder, v: &[u8], expected: &str) {
+        let encoded = encoder.encode_u8(v);
+        assert_eq!(encoded, expected);
+        let width = encoder.wrap_at.unwrap();
+        for line in encoded.lines() {
+            // unless it is a single long escape
+            let content = line.strip_suffix(encoder.escape_char).unwrap_or(line);
+            let single = super::token_len(encoder, content) == content.len();
+            assert!(line.chars().count() <= width || single, "{:?}", line);
+        }
+
+        let mut decoder = Decoder::new();
+        decoder.allow_line_continuation = true;
+        decoder.escape_char = encoder.escape_char;
+        decoder.x_digits = encoder.x_digits;
+        assert_eq!(decoder.decode_u8(&encoded).unwrap(), v);
+    }
+
+    #[test]
+    fn sanity_wrap() {
+        let encoder = Encoder::new().with_wrap_at(Some(8));
+        assert_wrapped(&encoder, b"", "");
+        assert_wrapped(&encoder, b"1234567", "1234567");
+        assert_wrapped(&encoder, b"12345678", "1234567\\\n8");
+        // escapes are never split
+        assert_wrapped(&encoder, b"12345\xFF\xFE", "12345\\\n\\xFF\\\n\\xFE");
+        assert_wrapped(&encoder, b"1234\\\n", "1234\\\\\\\n\\n");
+        assert_wrapped(&encoder, "ab¡¢£¤¥¦§¨".as_bytes(), "ab¡¢£¤¥\\\n¦§¨");
+
+        let encoder = Encoder::pretty()
+            .with_wrap_at(Some(6))
+            .with_escape_supplementary(true);
+        assert_wrapped(
+            &encoder,
+            "abc\nabcdefg\u{1F600}".as_bytes(),
+            "abc\nabcde\\\nfg\\\n\\u{1F600}",
+        );
+        let encoder = encoder.with_escape_supplementary_long(true);
+        assert_wrapped(&encoder, "ab\u{1F600}".as_bytes(), "ab\\\n\\U0001F600");
+
+        let encoder = Encoder::new().with_wrap_at(Some(6)).with_x_digits(4);
+        assert_wrapped(&encoder, b"a\xFF\xFE\xFD", "a\\\n\\xFFFE\\\n\\xFD");
+        let encoder = encoder.with_escape_char('%');
+        assert_wrapped(&encoder, b"ab\xFF\xFEc%", "ab%\n%xFFFE%\nc%%");
+    }
+}
@@ -5,9 +5,6 @@ extern crate proptest;
 extern crate stfu8;
 
 use std::str;
-use std::u16;
-use std::u32;
-use std::u8;
 
 const LEAD_MIN: u16 = 0xD800;
 // const LEAD_MAX: u16 = 0xDBFF;
@@ -22,6 +19,11 @@ fn assert_u8_round(v: &[u8]) {
     let _ = str::from_utf8(encoded.as_bytes()).unwrap();
     let result = stfu8::decode_u8(&encoded).unwrap();
     assert_eq!(v, result.as_slice());
+    assert_eq!(v, &*stfu8::decode_u8_boxed(&encoded).unwrap());
+
+    let mut buf = encoded.into_bytes();
+    stfu8::decode_u8_in_place(&mut buf).unwrap();
+    assert_eq!(v, buf.as_slice());
 }
 
 fn assert_u8_round_pretty(v: &[u8]) {
@@ -38,6 +40,14 @@ fn assert_u16_round(v: &[u16]) {
     let _ = str::from_utf8(encoded.as_bytes()).unwrap();
     let result = stfu8::decode_u16(&encoded).unwrap();
     assert_eq!(v, result.as_slice());
+
+    let encoder = stfu8::Encoder::new().with_short_u_escapes(true);
+    let mut decoder = stfu8::Decoder::new();
+    decoder.short_u_escapes = true;
+    let encoded = encoder.encode_u16(v);
+    assert_eq!(decoder.decode_u16(&encoded).unwrap(), v);
+    let encoded = encoder.with_ascii_only(true).encode_u16(v);
+    assert_eq!(decoder.decode_u16(&encoded).unwrap(), v);
 }
 
 fn assert_u16_round_pretty(v: &[u16]) {
@@ -54,12 +64,49 @@ proptest! {
         assert_u8_round(s.as_bytes());
         assert_u8_round_pretty(s.as_bytes());
 
+        let ascii = stfu8::Encoder::new().with_ascii_only(true).encode_u8(s.as_bytes());
+        assert!(ascii.is_ascii());
+        assert_eq!(stfu8::decode_u8(&ascii).unwrap(), s.as_bytes());
+
         let utf16: Vec<u16> = s.encode_utf16().collect();
         assert_u16_round(&utf16);
         assert_u16_round_pretty(&utf16);
     }
 }
 
+proptest! {
+    #[test]
+    fn fuzz_supplementary_long(ref s in "[a-f0-9\u{1F600}-\u{1F64F}\u{10000}-\u{10FFFF}]{0,100}") {
+        let encoder = stfu8::Encoder::new().with_escape_supplementary_long(true);
+        let encoded = encoder.encode_u8(s.as_bytes());
+        assert!(encoded.chars().all(|c| c.len_utf8() < 4));
+        assert_eq!(stfu8::decode_u8(&encoded).unwrap(), s.as_bytes());
+
+        let utf16: Vec<u16> = s.encode_utf16().collect();
+        assert_eq!(encoder.encode_u16(&utf16), encoded);
+        assert_eq!(stfu8::decode_u16(&encoded).unwrap(), utf16);
+    }
+}
+
+proptest! {
+    #[test]
+    /// The hex digits of escapes are case-insensitive
+    fn fuzz_u8_lowercase_hex(ref v in proptest::collection::vec(0..256_u32, 0..300)) {
+        // without ASCII letters, only the escapes are affected by changing the case
+        let v: Vec<u8> = v
+            .iter()
+            .map(|i| *i as u8)
+            .filter(|b| !b.is_ascii_alphabetic())
+            .collect();
+        let encoded = stfu8::encode_u8(&v);
+        assert_eq!(stfu8::decode_u8(&encoded.to_ascii_lowercase()).unwrap(), v);
+
+        let v16: Vec<u16> = v.iter().map(|b| u16::from(*b) << 8 | 0xD8).collect();
+        let encoded = stfu8::encode_u16(&v16);
+        assert_eq!(stfu8::decode_u16(&encoded.to_ascii_lowercase()).unwrap(), v16);
+    }
+}
+
 proptest! {
     #[test]
     fn fuzz_u8_binary(ref v in proptest::collection::vec(0..256_u32, 0..300)) {
@@ -73,12 +120,373 @@ proptest! {
     }
 }
 
+/// An `Encoder` with the escape flags taken from `flags` and the other settings from `style`,
+/// and a `Decoder` for its output.
+fn configured(
+    flags: &[bool],
+    style: usize,
+    wrap_at: Option<usize>,
+) -> (stfu8::Encoder, stfu8::Decoder) {
+    let mut encoder = stfu8::Encoder::new()
+        .with_encode_tab(flags[0])
+        .with_encode_line_feed(flags[1])
+        .with_encode_cariage(flags[2])
+        .with_encode_null(flags[3])
+        .with_encode_form_feed(flags[4])
+        .with_encode_vtab(flags[5])
+        .with_hex_only_controls(flags[6])
+        .with_escape_supplementary(flags[7])
+        .with_escape_supplementary_long(flags[8])
+        .with_short_u_escapes(flags[9])
+        .with_escape_del(flags[10])
+        .with_ascii_only(flags[11])
+        .with_escape_edge_spaces(flags[12])
+        .with_escape_line_separators(flags[13])
+        .with_literal_controls(if flags[14] { b"\x1B\x00" } else { b"" });
+    encoder = match style {
+        0 => encoder,
+        1 => encoder.with_escape_char('%'),
+        2 => encoder.with_x_digits(4),
+        _ => encoder.with_escape_style(stfu8::EscapeStyle::Percent),
+    };
+    if encoder.escape_style == stfu8::EscapeStyle::Backslash {
+        encoder = encoder.with_wrap_at(wrap_at);
+    }
+    let mut decoder = stfu8::Decoder::new();
+    decoder.escape_char = encoder.escape_char;
+    decoder.escape_style = encoder.escape_style;
+    decoder.x_digits = encoder.x_digits;
+    decoder.short_u_escapes = encoder.short_u_escapes;
+    decoder.allow_line_continuation = encoder.wrap_at.is_some();
+    (encoder, decoder)
+}
+
+proptest! {
+    #[test]
+    /// Every combination of settings round trips
+    fn fuzz_u8_configured(
+        ref flags in proptest::collection::vec(proptest::bool::ANY, 15),
+        style in 0..4_usize,
+        wrap_at in proptest::option::of(16..40_usize),
+        ref s in ".{0,50}",
+        ref special in "[ \t\n\r\x00\x0B\x0C\x1B\x7F\\\\%a0é\u{2028}\u{1F600}]{0,50}",
+        ref v in proptest::collection::vec(0..256_u32, 0..50),
+    ) {
+        let (encoder, decoder) = configured(flags, style, wrap_at);
+        let mut bytes: Vec<u8> = special.bytes().collect();
+        bytes.extend(v.iter().map(|i| *i as u8));
+        bytes.extend(s.bytes());
+        bytes.extend(special.bytes());
+        let encoded = encoder.encode_u8(&bytes);
+        assert_eq!(decoder.decode_u8(&encoded).unwrap(), bytes);
+    }
+}
+
+proptest! {
+    #[test]
+    /// Every combination of settings round trips
+    fn fuzz_u16_configured(
+        ref flags in proptest::collection::vec(proptest::bool::ANY, 15),
+        style in 0..3_usize,
+        wrap_at in proptest::option::of(16..40_usize),
+        ref s in ".{0,50}",
+        ref special in "[ \t\n\r\x00\x0B\x0C\x1B\x7F\\\\%a0é\u{2028}\u{1F600}]{0,50}",
+        ref v in proptest::collection::vec(LEAD_MIN..(TRAIL_MAX + 1), 0..20),
+    ) {
+        let (encoder, mut decoder) = configured(flags, style, wrap_at);
+        // `x_digits` only applies to `encode_u8`
+        decoder.x_digits = 2;
+        let mut v16: Vec<u16> = special.encode_utf16().collect();
+        v16.extend_from_slice(v);
+        v16.extend(s.encode_utf16());
+        v16.extend(special.encode_utf16());
+        let encoded = encoder.encode_u16(&v16);
+        assert_eq!(decoder.decode_u16(&encoded).unwrap(), v16);
+    }
+}
+
+proptest! {
+    #[test]
+    /// Encoding in chunks is the same as encoding all at once
+    fn fuzz_u8_stream(ref v in proptest::collection::vec(0..256_u32, 0..300), size in 1..10_usize) {
+        let v: Vec<u8> = v.iter().map(|i| *i as u8).collect();
+        let mut stream = stfu8::Encoder::new().encode_u8_stream();
+        let mut encoded = String::new();
+        for chunk in v.chunks(size) {
+            encoded += &stream.push(chunk);
+        }
+        encoded += &stream.finish();
+        assert_eq!(encoded, stfu8::encode_u8(&v));
+    }
+}
+
+proptest! {
+    #[test]
+    fn fuzz_u8_percent(ref v in proptest::collection::vec(0..256_u32, 0..300)) {
+        let v: Vec<u8> = v.iter().map(|i| *i as u8).collect();
+        let mut decoder = stfu8::Decoder::new();
+        decoder.escape_style = stfu8::EscapeStyle::Percent;
+        decoder.escape_char = '%';
+        for encoder in &[stfu8::Encoder::new(), stfu8::Encoder::pretty()] {
+            let encoder = encoder.with_escape_style(stfu8::EscapeStyle::Percent);
+            let encoded = encoder.encode_u8(&v);
+            assert_eq!(decoder.decode_u8(&encoded).unwrap(), v);
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    /// `Display` and `encode_u8` share one encoder and must produce identical output
+    fn fuzz_u8_display(ref v in proptest::collection::vec(0..256_u32, 0..300)) {
+        let v: Vec<u8> = v.iter().map(|i| *i as u8).collect();
+        assert_eq!(format!("{}", stfu8::Stfu8(&v)), stfu8::encode_u8(&v));
+        assert_eq!(format!("{}", stfu8::Stfu8Pretty(&v)), stfu8::encode_u8_pretty(&v));
+    }
+}
+
+proptest! {
+    #[test]
+    fn fuzz_encoded_len_u8(ref v in proptest::collection::vec(0..256_u32, 0..300)) {
+        let v: Vec<u8> = v.iter().map(|i| *i as u8).collect();
+        assert_eq!(stfu8::encoded_len_u8(&v), stfu8::encode_u8(&v).len());
+        for encoder in &[
+            stfu8::Encoder::new().with_x_digits(8).with_escape_supplementary_long(true),
+            stfu8::Encoder::pretty().with_escape_supplementary(true).with_ascii_only(true),
+            stfu8::Encoder::new().with_hex_only_controls(true),
+        ] {
+            assert!(encoder.encode_u8(&v).len() <= v.len() * stfu8::MAX_EXPANSION_U8);
+        }
+        for encoder in &[
+            stfu8::Encoder::pretty().with_encode_null(true),
+            stfu8::Encoder::new().with_x_digits(6).with_escape_supplementary(true),
+            stfu8::Encoder::new().with_ascii_only(true),
+        ] {
+            assert_eq!(encoder.encoded_len_u8(&v), encoder.encode_u8(&v).len());
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn fuzz_encode_char(c in proptest::char::any()) {
+        let mut buf = [0; 4];
+        let bytes = c.encode_utf8(&mut buf).as_bytes();
+        assert_eq!(stfu8::encode_char(c), stfu8::encode_u8(bytes));
+        for encoder in &[
+            stfu8::Encoder::pretty().with_literal_controls(b"\x1B"),
+            stfu8::Encoder::new().with_encode_null(true).with_escape_del(false),
+            stfu8::Encoder::new().with_escape_char('%').with_x_digits(4),
+            stfu8::Encoder::pretty().with_escape_supplementary(true),
+            stfu8::Encoder::new().with_escape_supplementary_long(true),
+        ] {
+            assert_eq!(encoder.encode_char(c), encoder.encode_u8(bytes));
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn fuzz_needs_encoding(ref s in "[ -~\t\n\x00\x7F¡\u{1F600}]{0,100}", ref v in proptest::collection::vec(0..256_u32, 0..20)) {
+        let binary: Vec<u8> = v.iter().map(|i| *i as u8).collect();
+        for v in &[s.as_bytes(), &binary] {
+            assert_eq!(stfu8::needs_encoding(v), stfu8::encode_u8(v).as_bytes() != *v);
+            for encoder in &[
+                stfu8::Encoder::pretty().with_literal_controls(b"\x00"),
+                stfu8::Encoder::pretty().with_hex_only_controls(true).with_escape_del(false),
+                stfu8::Encoder::new().with_escape_char('%').with_escape_supplementary(true),
+                stfu8::Encoder::pretty().with_wrap_at(Some(40)),
+            ] {
+                assert_eq!(encoder.needs_encoding(v), encoder.encode_u8(v).as_bytes() != *v);
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    /// `encode_str` only skips the validation, the output must be identical to `encode_u8`
+    fn fuzz_encode_str(ref s in ".{0,300}", ref ascii in "[\\x00-\\x7F¡\u{1F600}\u{2028}]{0,300}") {
+        let s = &format!("{}{}", ascii, s);
+        assert_eq!(stfu8::encode_str(s), stfu8::encode_u8(s.as_bytes()));
+        for encoder in &[
+            stfu8::Encoder::pretty(),
+            stfu8::Encoder::pretty().with_x_digits(4).with_escape_supplementary(true),
+            stfu8::Encoder::new().with_escape_char('%').with_literal_controls(b"\x1B"),
+            stfu8::Encoder::pretty().with_x_digits(4).with_ascii_only(true),
+            stfu8::Encoder::pretty().with_escape_line_separators(true),
+        ] {
+            assert_eq!(encoder.encode_str(s), encoder.encode_u8(s.as_bytes()));
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    /// `encode_chars` must be identical to `encode_u8` of the collected chars
+    fn fuzz_encode_chars(ref s in ".{0,300}", ref ascii in "[\\x00-\\x7F ¡\u{1F600}\u{2028}]{0,300}") {
+        let s = &format!("{}{}", ascii, s);
+        assert_eq!(stfu8::encode_chars(s.chars()), stfu8::encode_u8(s.as_bytes()));
+        for encoder in &[
+            stfu8::Encoder::pretty(),
+            stfu8::Encoder::pretty().with_x_digits(4).with_escape_supplementary(true),
+            stfu8::Encoder::new().with_escape_char('%').with_literal_controls(b"\x1B"),
+            stfu8::Encoder::pretty().with_x_digits(4).with_ascii_only(true),
+            stfu8::Encoder::new().with_escape_style(stfu8::EscapeStyle::Percent),
+            stfu8::Encoder::pretty().with_escape_edge_spaces(true).with_wrap_at(Some(20)),
+            stfu8::Encoder::pretty().with_escape_line_separators(true).with_x_digits(4),
+        ] {
+            assert_eq!(encoder.encode_chars(s.chars()), encoder.encode_u8(s.as_bytes()));
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    /// Re-encoding with the escapes of the decoded input reproduces the input
+    fn fuzz_u8_masked(ref v in proptest::collection::vec(0..256_u32, 0..300)) {
+        let v: Vec<u8> = v.iter().map(|i| *i as u8).collect();
+        let mut decoder = stfu8::Decoder::new();
+        for encoder in &[stfu8::Encoder::new(), stfu8::Encoder::pretty().with_x_digits(4)] {
+            decoder.x_digits = encoder.x_digits;
+            let encoded = encoder.encode_u8(&v);
+            let (decoded, mask) = decoder.decode_u8_masked(&encoded).unwrap();
+            assert_eq!(&decoded, &v);
+            assert_eq!(encoder.encode_u8_masked(&decoded, &mask), encoded);
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    /// Decoding arbitrary text after the start of an escape can fail, but never panic
+    fn fuzz_escape_no_panic(ref prefix in ".{0,3}", ref escape in "(x|u|u\\{)?", ref s in "([0-9a-fA-F}]|.){0,10}") {
+        let s = format!("{}\\{}{}", prefix, escape, s);
+        let _ = stfu8::decode_u8(&s);
+        let _ = stfu8::decode_u16(&s);
+        let _ = stfu8::decode_u8_as_str(&s);
+        let _ = stfu8::decode_to_lossy_string(&s);
+        let _ = stfu8::validate_stfu8(&s);
+
+        let mut buf = s.as_bytes().to_vec();
+        match stfu8::decode_u8_in_place(&mut buf) {
+            Ok(()) => assert_eq!(buf, stfu8::decode_u8(&s).unwrap()),
+            Err(err) => {
+                assert_eq!(buf, s.as_bytes());
+                assert_eq!(Err(err), stfu8::decode_u8(&s));
+            }
+        }
+
+        let (prefix, consumed) = stfu8::decode_u8_prefix(&s);
+        assert_eq!(stfu8::decode_u8(&s[..consumed]).unwrap(), prefix);
+        if consumed < s.len() {
+            assert_eq!(stfu8::decode_u8(&s).unwrap_err().index(), consumed);
+        }
+
+        let iter: Result<Vec<u8>, _> = stfu8::decode_u8_iter(&s).collect();
+        assert_eq!(iter, stfu8::decode_u8(&s));
+
+        // the first collected error is the one which stops `decode_u8`
+        let (decoded, errors) = stfu8::decode_u8_collect_errors(&s);
+        match stfu8::decode_u8(&s) {
+            Ok(v) => assert_eq!((v, errors), (decoded, vec![])),
+            Err(err) => assert_eq!(errors.first(), Some(&err)),
+        }
+
+        let mut decoder = stfu8::Decoder::new();
+        decoder.x_digits = 4;
+        decoder.recombine_surrogates = true;
+        let _ = decoder.decode_u8(&s);
+        let _ = decoder.decode_u16(&s);
+
+        // a lowercase digit is reported inside of its escape, but the prefix stops before it
+        decoder.require_uppercase_hex = true;
+        let _ = decoder.decode_u16(&s);
+        let (_, errors) = decoder.decode_u8_collect_errors(&s);
+        let (prefix, consumed) = decoder.decode_u8_prefix(&s);
+        assert_eq!(decoder.decode_u8(&s[..consumed]).unwrap(), prefix);
+        match decoder.decode_u8(&s) {
+            Ok(v) => assert_eq!((v, consumed), (prefix, s.len())),
+            Err(err) => {
+                assert!(err.index() >= consumed);
+                assert_eq!(errors.first(), Some(&err));
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    /// `encode_u8` copies runs of ASCII at once, the iterator encodes each char separately
+    fn fuzz_u8_iter(ref s in "[ -~]{0,20}", ref v in proptest::collection::vec(0..256_u32, 0..100)) {
+        let mut v: Vec<u8> = v.iter().map(|i| *i as u8).collect();
+        v.extend_from_slice(s.as_bytes());
+        v.extend_from_slice(s.as_bytes());
+        let iter: String = stfu8::encode_u8_iter(&v).collect();
+        assert_eq!(iter, stfu8::encode_u8(&v));
+    }
+}
+
+proptest! {
+    #[test]
+    /// The output is valid UTF-8 by construction, also when it is written as bytes. The input
+    /// mixes whole chars (including 4 byte ones), truncated chars and arbitrary bytes.
+    fn fuzz_encode_valid_utf8(
+        ref pieces in proptest::collection::vec(
+            (proptest::char::any(), proptest::char::range('\u{10000}', '\u{10FFFF}'), 0..4_usize, 0..256_u32),
+            0..100,
+        ),
+    ) {
+        use stfu8::EscapeSink;
+
+        let mut v: Vec<u8> = Vec::new();
+        for (c, supplementary, kind, b) in pieces {
+            let mut buf = [0; 4];
+            match kind {
+                0 => v.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+                1 => v.extend_from_slice(supplementary.encode_utf8(&mut buf).as_bytes()),
+                // all but the last byte of the char
+                2 => {
+                    let bytes = supplementary.encode_utf8(&mut buf).as_bytes();
+                    v.extend_from_slice(&bytes[..(bytes.len() - 1)]);
+                }
+                _ => v.push(*b as u8),
+            }
+        }
+        for encoder in &[
+            stfu8::Encoder::new(),
+            stfu8::Encoder::pretty().with_x_digits(4),
+            stfu8::Encoder::new().with_escape_supplementary(true),
+        ] {
+            let mut out: Vec<u8> = Vec::new();
+            out.write_escaped(encoder, &v).unwrap();
+            let encoded = str::from_utf8(&out).unwrap();
+            assert_eq!(encoded, encoder.encode_u8(&v));
+            let mut decoder = stfu8::Decoder::new();
+            decoder.x_digits = encoder.x_digits;
+            assert_eq!(decoder.decode_u8(encoded).unwrap(), v);
+        }
+    }
+}
+
 proptest! {
     #[test]
     fn fuzz_u16_binary(ref v in proptest::collection::vec(0..(u32::from(u16::MAX) + 1), 0..300)) {
         let v: Vec<u16> = v.iter().map(|i| *i as u16).collect();
         assert_u16_round(v.as_slice());
         assert_u16_round_pretty(v.as_slice());
+
+        let ascii = stfu8::Encoder::new().with_ascii_only(true).encode_u16(&v);
+        assert!(ascii.is_ascii());
+        assert_eq!(stfu8::decode_u16(&ascii).unwrap(), v);
+
+        for encoder in &[
+            stfu8::Encoder::new().with_ascii_only(true),
+            stfu8::Encoder::new().with_escape_supplementary_long(true),
+            stfu8::Encoder::new().with_hex_only_controls(true),
+        ] {
+            assert!(encoder.encode_u16(&v).len() <= v.len() * stfu8::MAX_EXPANSION_U16);
+        }
     }
 }
 
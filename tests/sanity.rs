@@ -1,15 +1,19 @@
 #![allow(unknown_lints)]
-#![allow(zero_width_space)]
+#![allow(clippy::invisible_characters)]
 
 #[macro_use]
 extern crate pretty_assertions;
 extern crate proptest;
 extern crate stfu8;
 
-use stfu8::{decode_u16, decode_u8, encode_u16, encode_u16_pretty, encode_u8, encode_u8_pretty};
+use stfu8::{
+    decode_to_lossy_string, decode_u16, decode_u8, decode_u8_as_str, decode_u8_boxed,
+    decode_u8_counted, decode_u8_n, decode_u8_prefix, decode_u8_records, encode_char, encode_u16,
+    encode_u16_pretty, encode_u8, encode_u8_pretty, is_valid_stfu8, max_passthrough_run,
+    validate_stfu8, DecodeErrorKind, Decoder, Encoder,
+};
 
 use std::str;
-use std::u16;
 
 static SAMPLE_2_0: &str = include_str!("unicode-sample-2.0.txt");
 static SAMPLE_3_2: &str = include_str!("unicode-sample-3.2.txt");
@@ -118,3 +122,313 @@ fn sanity_u8_decode() {
     assert!(decode_u8(r"foo\foo").is_err());
     assert!(decode_u8(r"foo\").is_err());
 }
+
+#[test]
+fn sanity_u8_decode_every_x_escape() {
+    // every `\x` escape is exactly one byte, even if it isn't valid UTF-8 on its own
+    for b in 0..=u8::MAX {
+        for escape in &[format!(r"\x{:02X}", b), format!(r"\x{:02x}", b)] {
+            assert_eq!(decode_u8(escape).unwrap(), [b], "{}", escape);
+            let s = format!("a{}b", escape);
+            assert_eq!(decode_u8(&s).unwrap(), [b'a', b, b'b'], "{}", s);
+            assert_eq!(decode_u16(escape).unwrap(), [u16::from(b)], "{}", escape);
+        }
+        assert_eq!(decode_u8(&encode_u8(&[b])).unwrap(), [b]);
+    }
+}
+
+#[test]
+fn sanity_u8_round_every_byte() {
+    // guards the escape tables: every byte, on its own and next to every other byte, must
+    // round trip with both the default and the pretty encoder
+    for encoder in &[Encoder::new(), Encoder::pretty()] {
+        for a in 0..=u8::MAX {
+            let encoded = encoder.encode_u8(&[a]);
+            assert_eq!(decode_u8(&encoded).unwrap(), [a], "{:?}", encoded);
+            for b in 0..=u8::MAX {
+                let encoded = encoder.encode_u8(&[a, b]);
+                assert_eq!(decode_u8(&encoded).unwrap(), [a, b], "{:?}", encoded);
+            }
+        }
+    }
+}
+
+#[test]
+fn sanity_u16_surrogate_escapes() {
+    let smiley: Vec<u16> = "\u{1F600}".encode_utf16().collect();
+    for (encoded, expected, reencoded) in &[
+        // escaped lead and escaped trail: the pair is decoded as-is
+        (r"\u00D83D\u00DE00", smiley.clone(), "\u{1F600}"),
+        // escaped lead and a literal char
+        (r"\u00D83Da", vec![0xD83D, 0x61], r"\u00D83Da"),
+        (
+            "\\u00D83D\u{1F600}",
+            vec![0xD83D, 0xD83D, 0xDE00],
+            "\\u00D83D\u{1F600}",
+        ),
+        // a literal supplementary-plane char
+        ("\u{1F600}", smiley.clone(), "\u{1F600}"),
+        // an escaped lone trail
+        (r"\u00DE00", vec![0xDE00], r"\u00DE00"),
+        (
+            r"a\u00DE00\u00D83D",
+            vec![0x61, 0xDE00, 0xD83D],
+            r"a\u00DE00\u00D83D",
+        ),
+    ] {
+        let decoded = decode_u16(encoded).unwrap();
+        assert_eq!(&decoded, expected, "{}", encoded);
+        assert_eq!(&encode_u16(&decoded), reencoded, "{}", encoded);
+        assert_eq!(&decode_u16(&encode_u16(&decoded)).unwrap(), expected);
+    }
+}
+
+#[test]
+fn sanity_decode_u8_boxed() {
+    for s in &["", r"foo\xFF\n¡\u01F600\u00D800", "a\\b"] {
+        assert_eq!(decode_u8_boxed(s).map(Vec::from), decode_u8(s), "{}", s);
+    }
+    let mut decoder = Decoder::new();
+    decoder.strict_u8 = true;
+    assert_eq!(
+        decoder.decode_u8_boxed(r"ab\u000100").unwrap_err(),
+        decoder.decode_u8(r"ab\u000100").unwrap_err()
+    );
+}
+
+#[test]
+fn sanity_decode_to_lossy_string() {
+    assert_eq!(decode_to_lossy_string(r"foo\xFF").unwrap(), "foo\u{FFFD}");
+    assert_eq!(
+        decode_to_lossy_string(r"\xFFfoo\n\xFE\xFDbar").unwrap(),
+        String::from_utf8_lossy(b"\xFFfoo\n\xFE\xFDbar")
+    );
+    assert_eq!(decode_to_lossy_string(r"\xC2\xA1 ¢").unwrap(), "¡ ¢");
+    assert_eq!(decode_to_lossy_string(r"\xC2¢").unwrap(), "\u{FFFD}¢");
+    assert_eq!(
+        decode_to_lossy_string(r"foo\u00D800").unwrap(),
+        "foo\u{FFFD}"
+    );
+    assert!(decode_to_lossy_string(r"foo\xFG").is_err());
+    assert!(decode_to_lossy_string(r"foo\").is_err());
+}
+
+#[test]
+fn sanity_decode_u8_as_str() {
+    assert_eq!(decode_u8_as_str("foo bar").unwrap(), "foo bar");
+    assert_eq!(
+        decode_u8_as_str(r"foo\tbar\\ ¡ ¢\n").unwrap(),
+        "foo\tbar\\ ¡ ¢\n"
+    );
+    assert_eq!(decode_u8_as_str(r"\xC2\xA1 \u0000A2").unwrap(), "¡ ¢");
+
+    let err = decode_u8_as_str(r"foo\xFF").unwrap_err();
+    assert_eq!(*err.kind(), DecodeErrorKind::NotUtf8);
+    assert_eq!(err.index(), 3);
+
+    let err = decode_u8_as_str(r"\xC2\xA1\xC2 ").unwrap_err();
+    assert_eq!(*err.kind(), DecodeErrorKind::NotUtf8);
+    assert_eq!(err.index(), 8);
+}
+
+#[test]
+fn sanity_validate() {
+    let sample = partial_encode(SAMPLE_3_2);
+    for s in &["", "foo", r"foo\xFF\t\\", r"\u02070E", &sample] {
+        assert!(is_valid_stfu8(s), "{}", s);
+        assert_eq!(validate_stfu8(s).is_ok(), decode_u8(s).is_ok());
+    }
+    for s in &[r"foo\", r"\xF", r"\xFG", r"\u00D800", r"\u220178"] {
+        assert!(!is_valid_stfu8(s), "{}", s);
+        let err = validate_stfu8(s).unwrap_err();
+        let expected = decode_u8(s).unwrap_err();
+        assert_eq!(
+            (err.kind(), err.index()),
+            (expected.kind(), expected.index())
+        );
+    }
+}
+
+#[test]
+fn sanity_decode_u8_records() {
+    let records = decode_u8_records("foo\r\nbar\\r\\n\n\r\n\\xFF baz\nqux\\\\").unwrap();
+    assert_eq!(
+        records,
+        vec![
+            b"foo".to_vec(),
+            b"bar\r\n".to_vec(),
+            b"".to_vec(),
+            b"\xFF baz".to_vec(),
+            b"qux\\".to_vec(),
+        ]
+    );
+    assert_eq!(decode_u8_records("foo\nbar\r\n").unwrap().len(), 2);
+    assert!(decode_u8_records("").unwrap().is_empty());
+
+    let err = decode_u8_records("foo\r\nbar\\q\n").unwrap_err();
+    assert_eq!(*err.kind(), DecodeErrorKind::UnescapedSlash);
+    assert_eq!(err.index(), 8);
+    assert_eq!((err.line(), err.column()), (2, 4));
+}
+
+#[test]
+fn sanity_decode_u8_counted() {
+    for s in &[
+        "",
+        "foo bar",
+        r"foo\tbar\\",
+        "¡ ¢ £",
+        r"\xC2\xA1 ¢\u01F600",
+        SAMPLE_2_0,
+    ] {
+        let s = &partial_encode(s);
+        let (bytes, count) = decode_u8_counted(s).unwrap();
+        assert_eq!(bytes, decode_u8(s).unwrap());
+        assert_eq!(count, str::from_utf8(&bytes).unwrap().chars().count());
+    }
+    assert_eq!(decode_u8_counted(r"\u01F600").unwrap().1, 1);
+
+    // invalid sequences count as one char each
+    for s in &[
+        r"\xFF",
+        r"foo\xFF\xFE",
+        r"\xC2¢",
+        r"\xE2\x82 \xC2",
+        r"\xF0\x9F\x98",
+    ] {
+        let (bytes, count) = decode_u8_counted(s).unwrap();
+        assert_eq!(
+            count,
+            String::from_utf8_lossy(&bytes).chars().count(),
+            "{}",
+            s
+        );
+        assert_eq!(count, decode_to_lossy_string(s).unwrap().chars().count());
+    }
+    assert!(decode_u8_counted(r"foo\").is_err());
+}
+
+#[test]
+fn sanity_decode_u8_n() {
+    // more levels than the default `max_nesting` of a `Decoder`
+    let mut s = "\x1B".to_string();
+    for _ in 0..10 {
+        s = encode_u8(s.as_bytes());
+    }
+    assert_eq!(decode_u8_n(&s, 10).unwrap(), b"\x1B");
+    assert_eq!(
+        Decoder::new().decode_u8_nested(&s, 10).unwrap_err().kind(),
+        &DecodeErrorKind::TooDeeplyNested
+    );
+    // still only decodes `times` levels
+    assert_eq!(decode_u8_n(&s, 9).unwrap(), br"\x1B");
+}
+
+#[test]
+fn sanity_encoder_with() {
+    let base = Encoder::pretty();
+    let tabs = base.with_encode_tab(true);
+    assert!(tabs.encode_tab);
+    assert!(!tabs.encode_line_feed && !tabs.encode_cariage);
+
+    let v = b"\tfoo\n\r\\\xFF\x7F";
+    assert_eq!(tabs.encode_u8(v), "\\tfoo\n\r\\\\\\xFF\\x7F");
+    assert_eq!(base.encode_u8(v), "\tfoo\n\r\\\\\\xFF\\x7F");
+    assert_eq!(base.encode_u8(v), encode_u8_pretty(v));
+
+    let encoder = Encoder::new()
+        .with_escape_char('%')
+        .with_x_digits(4)
+        .with_literal_controls(b"\x1B");
+    assert_eq!(encoder.encode_u8(b"\x1B%\xFF\xFE"), "\x1B%%%xFFFE");
+}
+
+#[test]
+fn sanity_max_passthrough_run() {
+    assert_eq!(max_passthrough_run("").unwrap(), 0);
+    assert_eq!(max_passthrough_run("foo bar").unwrap(), 7);
+    assert_eq!(max_passthrough_run(r"\\").unwrap(), 0);
+    assert_eq!(max_passthrough_run(r"ab\ncdef\\g\u01F600hi").unwrap(), 4);
+    assert_eq!(max_passthrough_run(r"ab\xFF¡ ¢ £\tcde").unwrap(), 8);
+    assert_eq!(max_passthrough_run(r"a\tbc\tdef").unwrap(), 3);
+    assert_eq!(
+        max_passthrough_run(&partial_encode(SAMPLE_3_2)).unwrap(),
+        SAMPLE_3_2.split('\\').map(str::len).max().unwrap()
+    );
+
+    let err = max_passthrough_run("foo bar\\").unwrap_err();
+    assert_eq!(*err.kind(), DecodeErrorKind::TrailingBackslash);
+    assert!(max_passthrough_run(r"\u00D800").is_err());
+}
+
+#[test]
+fn sanity_decode_error_eq() {
+    let err = decode_u8(r"foo\nbar\q").unwrap_err();
+    let cached = err.clone();
+    assert_eq!(err, cached);
+    assert_eq!(err.to_string(), cached.to_string());
+    assert_eq!(err, decode_u8(r"foo\nbar\q").unwrap_err());
+    // the location is part of the error
+    assert_ne!(err, decode_u8(r"foo\nba\q").unwrap_err());
+    assert_ne!(err, decode_u8(r"foo\nbar\").unwrap_err());
+}
+
+#[test]
+#[allow(deprecated)]
+fn sanity_decode_error_deprecated_fields() {
+    // the fields stay readable until they are made private
+    let err = decode_u8(r"foo\q").unwrap_err();
+    assert_eq!((&err.kind, err.index), (err.kind(), err.index()));
+}
+
+#[test]
+fn sanity_encode_char_ascii() {
+    // the ASCII chars are the only ones with any special handling
+    let pretty = Encoder::pretty();
+    for b in 0..0x80_u8 {
+        assert_eq!(encode_char(b as char), encode_u8(&[b]), "{:?}", b);
+        assert_eq!(pretty.encode_char(b as char), pretty.encode_u8(&[b]));
+    }
+    assert_eq!(encode_char('\u{1F600}'), "\u{1F600}");
+    assert_eq!(encode_char('\u{85}'), "\u{85}");
+}
+
+#[test]
+fn sanity_decode_u8_prefix() {
+    assert_eq!(decode_u8_prefix(""), (vec![], 0));
+    assert_eq!(decode_u8_prefix(r"\"), (vec![], 0));
+    assert_eq!(decode_u8_prefix(r"a\x4"), (b"a".to_vec(), 1));
+    assert_eq!(
+        decode_u8_prefix(r"¡\u00D800b"),
+        ("¡".as_bytes().to_vec(), 2)
+    );
+    assert_eq!(decode_u8_prefix(r"a\\\xFF\qb"), (b"a\\\xFF".to_vec(), 7));
+
+    // fields delimited by an invalid escape
+    let mut s = r"one\ttwo\|three\xFF\|";
+    let mut fields = Vec::new();
+    while !s.is_empty() {
+        let (field, consumed) = decode_u8_prefix(s);
+        fields.push(field);
+        assert!(s[consumed..].starts_with(r"\|"));
+        s = &s[(consumed + 2)..];
+    }
+    assert_eq!(fields, [b"one\ttwo".to_vec(), b"three\xFF".to_vec()]);
+}
+
+#[test]
+fn sanity_config_traits() {
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    struct Config {
+        encoder: Encoder,
+        decoder: Decoder,
+    }
+    let config = Config::default();
+    assert_eq!(config.encoder, Encoder::new());
+    assert_eq!(config.decoder, Decoder::new());
+
+    let mut changed = config;
+    changed.decoder.strip_bom = true;
+    assert_ne!(changed, config);
+    assert_eq!(changed.encoder, config.encoder);
+}